@@ -1,12 +1,16 @@
-use orderbook::prelude::{Empty, OrderBookClient};
+use orderbook::prelude::{OrderBookClient, SummaryRequest};
 use tonic::Request;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut client = OrderBookClient::connect("http://[::1]:12000").await?;
 
+    let request = SummaryRequest {
+        symbol: std::env::args().nth(1).unwrap_or_default(),
+        ..SummaryRequest::default()
+    };
     let mut stream = client
-        .book_summary(Request::new(Empty {}))
+        .book_summary(Request::new(request))
         .await?
         .into_inner();
 
@@ -10,6 +10,7 @@ use std::convert::TryFrom;
 use std::env;
 use std::path::Path;
 
+use crate::order_book::{normalize_symbol, FxRate, Instrument};
 use crate::prelude::Error;
 
 /// Configuration type.
@@ -17,6 +18,21 @@ use crate::prelude::Error;
 pub struct Configuration {
     pub result_size: usize,
     pub exchanges: Vec<ExchangeConfig>,
+    /// Seconds the exchange channels stay subscribed after the last client
+    /// disconnects.
+    pub linger_secs: Option<u64>,
+    /// Number of summaries a client may fall behind, unless requested, at most
+    /// the summaries kept by the hub.
+    pub max_lag: Option<usize>,
+    /// Reference data of the instruments.
+    #[serde(default)]
+    pub instruments: Vec<Instrument>,
+    /// File of reference data of more instruments.
+    pub instruments_path: Option<String>,
+    /// Sources of the rates converting the prices of the exchanges quoting an
+    /// instrument in another currency.
+    #[serde(default)]
+    pub fx_rates: Vec<FxRate>,
     pub server: Server,
 }
 
@@ -30,8 +46,46 @@ pub struct Server {
 pub struct ExchangeConfig {
     pub exchange: String,
     pub channel: String,
+    /// Other channels of the exchange, each streamed on its own connection
+    /// opened on the first subscription to the channel.
+    #[serde(default)]
+    pub channels: Vec<String>,
     pub url: String,
+    /// REST endpoint of the depth snapshot, for exchanges streaming diffs.
+    pub snapshot_url: Option<String>,
+    /// Seconds between two pings of the connection.
+    pub ping_interval_secs: Option<u64>,
+    /// Seconds without message before the connection is recycled.
+    pub idle_timeout_secs: Option<u64>,
     pub credential: Option<Credential>,
+    /// Symbol of the instrument of the channel, when it is mapped by the
+    /// instrument registry.
+    #[serde(skip)]
+    pub instrument: Option<String>,
+}
+
+impl ExchangeConfig {
+    /// Returns the configuration of each channel of the exchange.
+    pub fn per_channel(&self) -> Vec<ExchangeConfig> {
+        std::iter::once(&self.channel)
+            .chain(&self.channels)
+            .map(|channel| ExchangeConfig {
+                channel: channel.clone(),
+                channels: vec![],
+                instrument: None,
+                ..self.clone()
+            })
+            .collect()
+    }
+
+    /// Returns the symbol of the instrument of the channel, such as `btcusd`
+    /// for `BTC-USD` or `btc/usd` unless it is mapped to another instrument.
+    pub fn symbol(&self) -> String {
+        match &self.instrument {
+            Some(symbol) => symbol.clone(),
+            None => normalize_symbol(&self.channel),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -108,3 +162,30 @@ impl Configuration {
         format!("{}:{}", self.server.hostname, self.server.port)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchange_config_is_split_per_channel() {
+        let config = ExchangeConfig {
+            exchange: "kraken".into(),
+            channel: "XBT/USD".into(),
+            channels: vec!["ETH/USD".into()],
+            url: "wss://ws.kraken.com".into(),
+            snapshot_url: None,
+            ping_interval_secs: None,
+            idle_timeout_secs: None,
+            credential: None,
+            instrument: None,
+        };
+
+        let channels = config.per_channel();
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[1].channel, "ETH/USD");
+        assert!(channels[1].channels.is_empty());
+        assert_eq!(channels[0].symbol(), "xbtusd");
+        assert_eq!(channels[1].symbol(), "ethusd");
+    }
+}
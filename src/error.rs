@@ -1,10 +1,14 @@
+use std::time::Duration;
+
 use tokio_tungstenite::tungstenite;
 
 /// Possible order book errors.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    /// Boxed as `tungstenite::Error` is large enough to make every `Result`
+    /// of the crate trip clippy's `result_large_err` lint.
     #[error(transparent)]
-    WsError(#[from] tungstenite::Error),
+    WsError(Box<tungstenite::Error>),
 
     #[error(transparent)]
     ParseError(#[from] serde_json::Error),
@@ -12,6 +16,30 @@ pub enum Error {
     #[error(transparent)]
     ConfigError(#[from] config::ConfigError),
 
+    #[error(transparent)]
+    HttpError(#[from] reqwest::Error),
+
+    #[error("sequence gap: expected update {expected}, received {received}")]
+    SequenceGap { expected: u64, received: u64 },
+
+    #[error("checksum mismatch: expected {expected}, computed {computed}")]
+    ChecksumMismatch { expected: u32, computed: u32 },
+
+    #[error("stale book: product sequence moved over {0} heartbeats without update")]
+    StaleBook(u32),
+
+    #[error("missed heartbeat: no message received for {0:?}")]
+    MissedHeartbeat(Duration),
+
+    #[error("unknown instrument: {0:?}")]
+    UnknownInstrument(String),
+
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
+
+impl From<tungstenite::Error> for Error {
+    fn from(err: tungstenite::Error) -> Self {
+        Self::WsError(Box::new(err))
+    }
+}
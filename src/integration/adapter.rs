@@ -0,0 +1,182 @@
+//! Exchange adapter types.
+//!
+//! This module defines the extension point of the exchange integrations. An
+//! [`ExchangeAdapter`] builds the messages sent to an exchange and decodes the
+//! messages it receives into book updates. Adapters are registered by name in
+//! an [`AdapterRegistry`] and selected with the `exchange` of the exchange
+//! configuration, so a venue can be added without changing this crate.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tungstenite::Message;
+
+use super::binance::BinanceAdapter;
+use super::bitfinex::BitfinexAdapter;
+use super::bitstamp::BitstampAdapter;
+use super::coinbase::CoinbaseAdapter;
+use super::huobi::HuobiAdapter;
+use super::kraken::KrakenAdapter;
+use super::transport::Compression;
+use crate::configuration::ExchangeConfig;
+use crate::prelude::{BookUpdate, Exchange, Result};
+
+/// The [`ExchangeAdapter`] trait encapsulates the protocol of an exchange.
+pub trait ExchangeAdapter: Send + Sync {
+    /// Returns the exchange of the adapter.
+    fn exchange(&self) -> Exchange;
+
+    /// Creates the message subscribing to the configured channel.
+    fn subscribe_message(&self, config: &ExchangeConfig) -> Result<Message>;
+
+    /// Creates the message unsubscribing from the configured channel.
+    fn unsubscribe_message(&self, config: &ExchangeConfig) -> Result<Message>;
+
+    /// Creates the message keeping the connection alive, if the exchange
+    /// expects one from the client.
+    fn heartbeat_message(&self) -> Option<Message> {
+        None
+    }
+
+    /// Returns the longest period without message before the connection is
+    /// considered lost, if the exchange sends heartbeats.
+    fn heartbeat_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns the compression of the binary frames of the exchange.
+    ///
+    /// Binary frames are decompressed into text frames before they are decoded.
+    fn compression(&self) -> Compression {
+        Compression::None
+    }
+
+    /// Creates a decoder of the messages of a connection.
+    fn decoder(&self, config: &ExchangeConfig) -> Result<Box<dyn BookDecoder>>;
+}
+
+/// The [`BookDecoder`] trait decodes the messages of a connection.
+///
+/// A decoder is created for each connection, so it can keep the state the
+/// exchange protocol requires, such as sequence numbers.
+#[async_trait]
+pub trait BookDecoder: Send {
+    /// Decodes a message into the updates to apply to the book.
+    ///
+    /// Messages that do not change the book, such as subscription replies,
+    /// decode to no update. A sequence gap, checksum mismatch or stale book
+    /// [`Error`](crate::error::Error) is returned when the book diverged from
+    /// the exchange book, so the channel is subscribed again.
+    async fn decode(&mut self, message: Message) -> Result<Vec<BookUpdate>>;
+
+    /// Returns the messages to send in reply to the decoded messages, such as
+    /// the pong of an application level ping.
+    fn replies(&mut self) -> Vec<Message> {
+        vec![]
+    }
+
+    /// Creates the messages unsubscribing from the channel of the connection,
+    /// for the exchanges identifying a subscription by an id of the
+    /// subscription reply.
+    ///
+    /// No message is created while the subscription reply is awaited, the
+    /// decoder then replies to it with the unsubscribe message. Returns `None`
+    /// to use the unsubscribe message of the adapter.
+    fn unsubscribe_messages(&mut self) -> Option<Vec<Message>> {
+        None
+    }
+}
+
+/// The [`AdapterRegistry`] type holds the exchange adapters by name.
+#[derive(Clone)]
+pub struct AdapterRegistry {
+    adapters: HashMap<Exchange, Arc<dyn ExchangeAdapter>>,
+}
+
+impl Default for AdapterRegistry {
+    /// Creates new registry of the adapters of the supported exchanges.
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry
+            .register(BinanceAdapter)
+            .register(BitstampAdapter)
+            .register(KrakenAdapter)
+            .register(CoinbaseAdapter)
+            .register(BitfinexAdapter)
+            .register(HuobiAdapter);
+        registry
+    }
+}
+
+impl AdapterRegistry {
+    /// Creates new empty registry.
+    pub fn new() -> Self {
+        Self {
+            adapters: HashMap::new(),
+        }
+    }
+
+    /// Registers an adapter, replacing the adapter of the same exchange.
+    pub fn register<A>(&mut self, adapter: A) -> &mut Self
+    where
+        A: ExchangeAdapter + 'static,
+    {
+        self.adapters.insert(adapter.exchange(), Arc::new(adapter));
+        self
+    }
+
+    /// Returns the adapter registered with the exchange name.
+    pub fn get(&self, name: &str) -> Result<Arc<dyn ExchangeAdapter>> {
+        let exchange = name.parse::<Exchange>()?;
+        let adapter = self
+            .adapters
+            .get(&exchange)
+            .ok_or_else(|| anyhow::anyhow!("no adapter registered for exchange '{}'", name))?;
+
+        Ok(Arc::clone(adapter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestAdapter;
+
+    impl ExchangeAdapter for TestAdapter {
+        fn exchange(&self) -> Exchange {
+            Exchange::new("test")
+        }
+
+        fn subscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+            Ok(Message::Text(format!("subscribe {}", config.channel)))
+        }
+
+        fn unsubscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+            Ok(Message::Text(format!("unsubscribe {}", config.channel)))
+        }
+
+        fn decoder(&self, _config: &ExchangeConfig) -> Result<Box<dyn BookDecoder>> {
+            Err(anyhow::anyhow!("not supported").into())
+        }
+    }
+
+    #[test]
+    fn registry_selects_adapter_by_name() {
+        let mut registry = AdapterRegistry::default();
+        assert!(registry.get("Test").is_err());
+
+        registry.register(TestAdapter);
+        assert_eq!(
+            registry.get("Test").unwrap().exchange(),
+            Exchange::new("test")
+        );
+        assert_eq!(
+            registry.get("binance").unwrap().exchange(),
+            Exchange::BINANCE
+        );
+        assert!(registry.get("").is_err());
+    }
+}
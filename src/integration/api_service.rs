@@ -5,108 +5,584 @@
 use async_trait::async_trait;
 use futures_util::{SinkExt, StreamExt};
 use std::collections::HashMap;
-use tokio::sync::{mpsc, oneshot};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::{self, Instant};
 use tokio_tungstenite::connect_async;
 use tungstenite::Message;
 
+use super::adapter::{AdapterRegistry, BookDecoder, ExchangeAdapter};
 use super::transport::WebSocketTransport;
-use super::transport::{StopSender, WebSocketStream};
+use super::transport::{Backoff, StopSender, WebSocketStream};
 use crate::configuration::ExchangeConfig;
-use crate::prelude::{Book, BookKind, Error, Exchange, Result};
+use crate::prelude::{BookBatch, BookUpdate, ConnectionState, Error, Exchange, Result};
+
+/// Delay before the first reconnection attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest delay between reconnection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Period between two pings of a connection, unless configured.
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Longest period without message before a connection is considered dead,
+/// unless configured or given by the exchange adapter.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Longest wait for a connection to open.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest wait for the reply to a close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest wait for the connections to close on stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of pending commands of a connection.
+const COMMAND_CAPACITY: usize = 8;
 
 pub struct ApiService {
     pub capacity: usize,
     pub(crate) services: Vec<ExchangeService>,
     pub send_on_stop: HashMap<String, StopSender>,
+    registry: AdapterRegistry,
 }
 
 impl ApiService {
-    /// Creates new [`ApiService`].
+    /// Creates new [`ApiService`] with the adapters of the supported exchanges.
     pub fn new(capacity: usize) -> Self {
+        Self::with_registry(capacity, AdapterRegistry::default())
+    }
+
+    /// Creates new [`ApiService`] with the adapters of a registry.
+    pub fn with_registry(capacity: usize, registry: AdapterRegistry) -> Self {
         Self {
             capacity,
             services: vec![],
             send_on_stop: HashMap::new(),
+            registry,
         }
     }
 
     /// Opens a connection to an exchange.
     #[tracing::instrument(name = "Connect to websocket", skip(self, config))]
     pub async fn connect(&mut self, config: &ExchangeConfig) -> Result<()> {
-        let (socket, _) = connect_async(&config.url).await.map_err(Error::WsError)?;
-        let socket = Box::pin(socket) as WebSocketStream;
+        let adapter = self.registry.get(&config.exchange)?;
+        let socket = open_socket(&config.url).await?;
+        self.push_service(config, adapter, Some(socket));
+        Ok(())
+    }
+
+    /// Adds a connection to an exchange, opened once the socket streams are
+    /// watched and its channel is first subscribed.
+    ///
+    /// A connection that fails to open is retried with an exponential backoff,
+    /// like a lost connection. Returns an error if no adapter is registered for
+    /// the exchange.
+    pub fn add(&mut self, config: &ExchangeConfig) -> Result<()> {
+        let adapter = self.registry.get(&config.exchange)?;
+        self.push_service(config, adapter, None);
+        Ok(())
+    }
+
+    fn push_service(
+        &mut self,
+        config: &ExchangeConfig,
+        adapter: Arc<dyn ExchangeAdapter>,
+        socket: Option<WebSocketStream>,
+    ) {
+        let (command_tx, command_rx) = mpsc::channel(COMMAND_CAPACITY);
         self.services.push(ExchangeService {
-            socket: Some(socket),
+            socket,
             config: config.clone(),
+            adapter,
+            subscribed: false,
+            commands: command_tx,
+            command_receiver: Some(command_rx),
         });
-        Ok(())
     }
 
+    /// Returns the sender of the commands of the connection to the channel of
+    /// an instrument on an exchange.
+    pub fn commands(
+        &self,
+        exchange: &Exchange,
+        symbol: &str,
+    ) -> Option<mpsc::Sender<ChannelCommand>> {
+        self.services
+            .iter()
+            .find(|service| {
+                &service.adapter.exchange() == exchange && service.config.symbol() == symbol
+            })
+            .map(ExchangeService::commands)
+    }
+
+    /// Watches the socket streams until a stop is requested.
+    ///
+    /// Each socket is read in order by its own exchange service, which opens the
+    /// connection on the first subscription if it was only added. On stop, the
+    /// services unsubscribe from their channel and close their connection.
     #[tracing::instrument(name = "Watch list of socket stream", skip(self, book_sender, stop))]
     pub async fn watch(
         &mut self,
-        book_sender: mpsc::Sender<(BookKind, Book)>,
+        book_sender: mpsc::Sender<BookBatch>,
         mut stop: oneshot::Receiver<bool>,
     ) {
-        let mut fut = futures_util::stream::select_all(
-            self.services.iter_mut().map(|s| s.socket.take().unwrap()),
-        );
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let streams = self.services.iter_mut().filter_map(|service| {
+            let socket = service.socket.take();
+            let commands = service.command_receiver.take()?;
+            let session = Session {
+                config: service.config.clone(),
+                subscribed: service.subscribed,
+                commands,
+                shutdown: shutdown_rx.clone(),
+                book_sender: book_sender.clone(),
+            };
+            let service: &ExchangeService = service;
+            Some(service.stream_books(socket, session))
+        });
+        let mut fut = futures_util::future::join_all(streams);
 
-        loop {
-            tokio::select! {
-                Some(message) = fut.next() => {
-                    tokio::spawn(Book::publish(book_sender.clone(), message));
-                },
-                _ = (&mut stop) => break,
-            }
+        tokio::select! {
+            _ = &mut fut => return,
+            _ = (&mut stop) => (),
+        }
+
+        let _ = shutdown_tx.send(true);
+        if time::timeout(SHUTDOWN_TIMEOUT, fut).await.is_err() {
+            tracing::warn!("exchange connections were not closed in time");
         }
     }
 }
 
+/// The [`ChannelCommand`] type is a change of the subscription of a running
+/// exchange connection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChannelCommand {
+    /// Subscribes to a channel, in place of the unsubscribed one.
+    Subscribe(String),
+
+    /// Unsubscribes from the current channel and clears the exchange books.
+    Unsubscribe,
+}
+
 /// Exchange service.
 pub struct ExchangeService {
     pub socket: Option<WebSocketStream>,
     pub config: ExchangeConfig,
+    pub adapter: Arc<dyn ExchangeAdapter>,
+    /// Whether the channel was subscribed before the socket is watched.
+    subscribed: bool,
+    commands: mpsc::Sender<ChannelCommand>,
+    command_receiver: Option<mpsc::Receiver<ChannelCommand>>,
 }
 
 #[async_trait]
 impl WebSocketTransport for ExchangeService {
     #[tracing::instrument(name = "Subscribe to channel", skip(self, message))]
     async fn subscribe(&mut self, message: Message) -> Result<()> {
-        self.socket.as_mut().unwrap().send(message).await?;
+        self.socket_mut()?.send(message).await?;
+        self.subscribed = true;
         Ok(())
     }
 
-    async fn unsubscribe(&self) -> Result<()> {
+    #[tracing::instrument(name = "Unsubscribe from channel", skip(self, message))]
+    async fn unsubscribe(&mut self, message: Message) -> Result<()> {
+        self.socket_mut()?.send(message).await?;
+        self.subscribed = false;
         Ok(())
     }
+
+    #[tracing::instrument(name = "Close connection", skip(self))]
+    async fn close(&mut self) -> Result<()> {
+        close_socket(self.socket_mut()?).await
+    }
+}
+
+/// The [`Session`] type is the state of the stream of an exchange service,
+/// kept across reconnections.
+struct Session {
+    /// Configuration of the current subscription.
+    config: ExchangeConfig,
+    /// Whether the channel is subscribed, and must be subscribed again on
+    /// reconnection.
+    subscribed: bool,
+    commands: mpsc::Receiver<ChannelCommand>,
+    shutdown: watch::Receiver<bool>,
+    book_sender: mpsc::Sender<BookBatch>,
+}
+
+impl Session {
+    /// Returns `true` if a stop was requested.
+    fn is_stopped(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Applies a command received without connection, to the subscription of
+    /// the next connection.
+    fn apply(&mut self, command: ChannelCommand) {
+        match command {
+            ChannelCommand::Subscribe(channel) => {
+                self.config.channel = channel;
+                self.subscribed = true;
+            }
+            ChannelCommand::Unsubscribe => self.subscribed = false,
+        }
+    }
+
+    /// Publishes the book updates of the subscribed instrument as one batch,
+    /// logging the failure.
+    ///
+    /// Empty batches are not published.
+    async fn publish(&self, updates: Vec<BookUpdate>) {
+        if updates.is_empty() {
+            return;
+        }
+        let batch = BookBatch::new(self.config.symbol(), updates);
+        if let Err(e) = self.book_sender.send(batch).await {
+            tracing::error!("failed to publish book: {}", e);
+        }
+    }
 }
 
 impl ExchangeService {
+    /// Publishes the books received on the socket until a stop is requested.
+    ///
+    /// Without socket, the connection is opened once the channel is
+    /// subscribed. When the connection fails to open, or the stream ends or
+    /// fails, the books of the exchange are cleared and the service reconnects
+    /// with an exponential backoff, then subscribes again to rebuild the books.
+    /// A connection lost while unsubscribed is only opened again on the next
+    /// subscription. Connection state changes are published with the books.
+    #[tracing::instrument(name = "Stream exchange books", skip(self, socket, session))]
+    async fn stream_books(&self, mut socket: Option<WebSocketStream>, mut session: Session) {
+        let exchange = self.adapter.exchange();
+        let mut backoff = Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF);
+
+        loop {
+            if socket.is_none() && !session.subscribed {
+                tokio::select! {
+                    Some(command) = session.commands.recv() => session.apply(command),
+                    _ = session.shutdown.changed() => return,
+                }
+                continue;
+            }
+            if socket.is_none() {
+                match self.reconnect(&session).await {
+                    Ok(reconnected) => {
+                        backoff.reset();
+                        socket = Some(reconnected);
+                    }
+                    Err(e) => tracing::error!(
+                        "connection to exchange '{}' failed: {}",
+                        self.config.exchange,
+                        e
+                    ),
+                }
+            }
+            if let Some(socket) = socket.take() {
+                session
+                    .publish(vec![BookUpdate::Connection(
+                        exchange.clone(),
+                        ConnectionState::Connected,
+                    )])
+                    .await;
+                match self.decode_books(socket, &mut session).await {
+                    Ok(()) if session.is_stopped() => {
+                        tracing::info!("stream of exchange '{}' stopped", self.config.exchange)
+                    }
+                    Ok(()) => {
+                        tracing::warn!("stream of exchange '{}' closed", self.config.exchange)
+                    }
+                    Err(e) => tracing::error!(
+                        "stream of exchange '{}' failed: {}",
+                        self.config.exchange,
+                        e
+                    ),
+                }
+                session
+                    .publish(vec![
+                        BookUpdate::Clear(exchange.clone()),
+                        BookUpdate::Connection(exchange.clone(), ConnectionState::Disconnected),
+                    ])
+                    .await;
+            }
+            if session.is_stopped() {
+                return;
+            }
+
+            let (attempt, delay) = backoff.next_delay();
+            let state = ConnectionState::Reconnecting { attempt, delay };
+            session
+                .publish(vec![BookUpdate::Connection(exchange.clone(), state)])
+                .await;
+            tokio::select! {
+                _ = time::sleep(delay) => (),
+                _ = session.shutdown.changed() => return,
+            }
+        }
+    }
+
+    /// Opens a new connection to the exchange and subscribes to the channel,
+    /// if it was subscribed.
+    #[tracing::instrument(name = "Reconnect to websocket", skip(self, session))]
+    async fn reconnect(&self, session: &Session) -> Result<WebSocketStream> {
+        let mut socket = open_socket(&session.config.url).await?;
+        if session.subscribed {
+            socket
+                .send(self.adapter.subscribe_message(&session.config)?)
+                .await?;
+        }
+
+        Ok(socket)
+    }
+
+    /// Decodes the messages received on the socket with the exchange adapter.
+    ///
+    /// The updates decoded from a message are published as one batch, so that
+    /// the message is applied to the book at once.
+    ///
+    /// Binary frames are decompressed before they are decoded, and the replies
+    /// of the decoder are sent on the socket. A message that fails to
+    /// decompress or decode is logged and skipped. When the decoder
+    /// reports a sequence gap or a checksum mismatch, the books of the exchange
+    /// are cleared and the channel is subscribed again to receive a new
+    /// snapshot.
+    ///
+    /// The connection is pinged periodically, and the stream fails when no
+    /// message is received within the idle timeout, so that a dead connection
+    /// is recycled. On stop, the channel is unsubscribed and the connection
+    /// closed.
+    async fn decode_books(&self, mut socket: WebSocketStream, session: &mut Session) -> Result<()> {
+        let mut decoder = self.adapter.decoder(&session.config)?;
+        let idle_timeout = self.idle_timeout();
+        let ping_interval = self.ping_interval();
+        let mut ping = time::interval_at(Instant::now() + ping_interval, ping_interval);
+        let mut deadline = Instant::now() + idle_timeout;
+
+        loop {
+            if session.is_stopped() {
+                return self.shutdown(socket, decoder.as_mut(), session).await;
+            }
+
+            let event = tokio::select! {
+                message = socket.next() => SocketEvent::Message(message),
+                Some(command) = session.commands.recv() => SocketEvent::Command(command),
+                _ = ping.tick() => SocketEvent::Ping,
+                _ = time::sleep_until(deadline) => SocketEvent::Idle,
+                _ = session.shutdown.changed() => continue,
+            };
+            let message = match event {
+                SocketEvent::Message(Some(message)) => message,
+                SocketEvent::Message(None) => return Ok(()),
+                SocketEvent::Command(ChannelCommand::Subscribe(channel)) => {
+                    session.config.channel = channel;
+                    session.subscribed = true;
+                    socket
+                        .send(self.adapter.subscribe_message(&session.config)?)
+                        .await?;
+                    decoder = self.adapter.decoder(&session.config)?;
+                    continue;
+                }
+                SocketEvent::Command(ChannelCommand::Unsubscribe) => {
+                    session.subscribed = false;
+                    let unsubscribe = self.unsubscribe_request(decoder.as_mut(), session);
+                    match unsubscribe {
+                        Ok(messages) => {
+                            for message in messages {
+                                socket.send(message).await?;
+                            }
+                        }
+                        Err(e) => tracing::warn!(
+                            "failed to unsubscribe from exchange '{}': {}",
+                            self.config.exchange,
+                            e
+                        ),
+                    }
+                    session
+                        .publish(vec![BookUpdate::Clear(self.adapter.exchange())])
+                        .await;
+                    continue;
+                }
+                SocketEvent::Ping => {
+                    socket.send(self.ping_message()).await?;
+                    continue;
+                }
+                SocketEvent::Idle => return Err(Error::MissedHeartbeat(idle_timeout)),
+            };
+            deadline = Instant::now() + idle_timeout;
+
+            let message = match self.adapter.compression().decode_frame(message?) {
+                Ok(message) => message,
+                Err(e) => {
+                    tracing::error!(
+                        "failed to read frame of exchange '{}': {}",
+                        self.config.exchange,
+                        e
+                    );
+                    continue;
+                }
+            };
+            let updates = match decoder.decode(message).await {
+                Ok(updates) => updates,
+                Err(
+                    e @ (Error::ChecksumMismatch { .. }
+                    | Error::SequenceGap { .. }
+                    | Error::StaleBook(_)),
+                ) if session.subscribed => {
+                    tracing::warn!(
+                        "resubscribing to exchange '{}': {}",
+                        self.config.exchange,
+                        e
+                    );
+                    for message in self.unsubscribe_request(decoder.as_mut(), session)? {
+                        socket.send(message).await?;
+                    }
+                    socket
+                        .send(self.adapter.subscribe_message(&session.config)?)
+                        .await?;
+                    vec![BookUpdate::Clear(self.adapter.exchange())]
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "failed to decode message of exchange '{}': {}",
+                        self.config.exchange,
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            for reply in decoder.replies() {
+                socket.send(reply).await?;
+            }
+            // The books still in flight when the channel was unsubscribed must
+            // not rebuild the cleared book.
+            if session.subscribed {
+                session.publish(updates).await;
+            }
+        }
+    }
+
+    /// Unsubscribes from the channel, if subscribed, then closes the
+    /// connection.
+    #[tracing::instrument(name = "Shutdown connection", skip(self, socket, decoder, session))]
+    async fn shutdown(
+        &self,
+        mut socket: WebSocketStream,
+        decoder: &mut dyn BookDecoder,
+        session: &Session,
+    ) -> Result<()> {
+        if session.subscribed {
+            let unsubscribe = self.unsubscribe_request(decoder, session);
+            match unsubscribe {
+                Ok(messages) => {
+                    for message in messages {
+                        socket.send(message).await?;
+                    }
+                }
+                Err(e) => tracing::warn!(
+                    "failed to unsubscribe from exchange '{}': {}",
+                    self.config.exchange,
+                    e
+                ),
+            }
+        }
+
+        close_socket(&mut socket).await
+    }
+
+    /// Creates the messages unsubscribing from the channel of the session,
+    /// given by the decoder of the connection if it knows the subscription.
+    fn unsubscribe_request(
+        &self,
+        decoder: &mut dyn BookDecoder,
+        session: &Session,
+    ) -> Result<Vec<Message>> {
+        match decoder.unsubscribe_messages() {
+            Some(messages) => Ok(messages),
+            None => Ok(vec![self.adapter.unsubscribe_message(&session.config)?]),
+        }
+    }
+
+    /// Returns the sender of the commands of the connection.
+    pub fn commands(&self) -> mpsc::Sender<ChannelCommand> {
+        self.commands.clone()
+    }
+
+    /// Returns the longest period without message before the connection is
+    /// considered dead.
+    fn idle_timeout(&self) -> Duration {
+        self.config
+            .idle_timeout_secs
+            .map(Duration::from_secs)
+            .or_else(|| self.adapter.heartbeat_timeout())
+            .unwrap_or(DEFAULT_IDLE_TIMEOUT)
+    }
+
+    /// Returns the period between two pings of the connection.
+    fn ping_interval(&self) -> Duration {
+        self.config
+            .ping_interval_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_PING_INTERVAL)
+    }
+
+    /// Returns the heartbeat of the exchange, or a WebSocket ping for the
+    /// exchanges without application level heartbeat.
+    fn ping_message(&self) -> Message {
+        self.adapter
+            .heartbeat_message()
+            .unwrap_or_else(|| Message::Ping(vec![]))
+    }
+
     /// Creates new message for based on exchange configuration.
     #[tracing::instrument(name = "Create new subscribe message", skip(self))]
     pub fn new_message(&self) -> Result<Message> {
-        let exchange = self.config.exchange.parse()?;
-        let message = match exchange {
-            Exchange::Bitstamp => Message::Text(
-                serde_json::json!({
-                    "event": "bts:subscribe",
-                    "data": {
-                        "channel": format!("order_book_{}", &self.config.channel)
-                    }
-                })
-                .to_string(),
-            ),
-            Exchange::Binance => Message::Text(
-                serde_json::json!({
-                    "method": "SUBSCRIBE",
-                    "params": [format!("{}@depth", &self.config.channel)],
-                    "id": 1
-                })
-                .to_string(),
-            ),
-        };
-
-        Ok(message)
+        self.adapter.subscribe_message(&self.config)
+    }
+
+    /// Creates new unsubscribe message based on exchange configuration.
+    #[tracing::instrument(name = "Create new unsubscribe message", skip(self))]
+    pub fn unsubscribe_message(&self) -> Result<Message> {
+        self.adapter.unsubscribe_message(&self.config)
     }
+
+    fn socket_mut(&mut self) -> Result<&mut WebSocketStream> {
+        Ok(self
+            .socket
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("not connected to exchange"))?)
+    }
+}
+
+/// Opens a connection, failing if it is not open within the connect timeout.
+async fn open_socket(url: &str) -> Result<WebSocketStream> {
+    let (socket, _) = time::timeout(CONNECT_TIMEOUT, connect_async(url))
+        .await
+        .map_err(|_| anyhow::anyhow!("connection not open after {:?}", CONNECT_TIMEOUT))??;
+    Ok(Box::pin(socket) as WebSocketStream)
+}
+
+/// Sends a close frame, then reads the socket until the exchange replies with
+/// its own close frame.
+async fn close_socket(socket: &mut WebSocketStream) -> Result<()> {
+    socket.close().await?;
+    let drain = async { while let Some(Ok(_)) = socket.next().await {} };
+    if time::timeout(CLOSE_TIMEOUT, drain).await.is_err() {
+        tracing::warn!("exchange did not reply to the close frame");
+    }
+
+    Ok(())
+}
+
+/// The [`SocketEvent`] type is an event of a supervised socket.
+enum SocketEvent {
+    Message(Option<std::result::Result<Message, tungstenite::Error>>),
+    Command(ChannelCommand),
+    Ping,
+    Idle,
 }
@@ -0,0 +1,449 @@
+//! Binance integration.
+//!
+//! This module implements the synchronisation of a local order book with the
+//! Binance diff depth stream.
+//!
+//! The `<symbol>@depth` stream only sends the levels that changed since the
+//! previous event. The book is rebuilt as documented by Binance:
+//!
+//! 1. Buffer the events received from the stream.
+//! 2. Fetch a depth snapshot from the REST API.
+//! 3. Drop the buffered events with a final update id older than the snapshot.
+//! 4. Apply the snapshot then the remaining events in order.
+//! 5. Resynchronise when the first update id of an event does not follow the
+//!    final update id of the previous one.
+//!
+//! A snapshot that cannot be fetched or applied is fetched again after a delay
+//! doubling with each attempt, so an unsynchronised book does not request the
+//! REST API on every event.
+//!
+//! The [`BinanceAdapter`] registers the Binance decoder, which runs this
+//! procedure for each connection.
+
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tungstenite::Message;
+
+use super::adapter::{BookDecoder, ExchangeAdapter};
+use super::event::{self, BinanceEvent};
+use crate::configuration::ExchangeConfig;
+use crate::prelude::{BookUpdate, Error, Exchange, Result};
+
+/// Number of levels requested in a depth snapshot.
+const SNAPSHOT_LIMIT: u32 = 1000;
+
+/// Delay before a depth snapshot is fetched again after a failed attempt.
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest delay between two attempts to fetch a depth snapshot.
+const SNAPSHOT_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// The [`DepthUpdate`] type is an event of the diff depth stream.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DepthUpdate {
+    #[serde(rename = "U")]
+    pub first_update_id: u64,
+    #[serde(rename = "u")]
+    pub final_update_id: u64,
+    #[serde(rename = "b")]
+    pub bids: Vec<(Decimal, Decimal)>,
+    #[serde(rename = "a")]
+    pub asks: Vec<(Decimal, Decimal)>,
+}
+
+/// The [`DepthSnapshot`] type is the response of the REST depth endpoint.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DepthSnapshot {
+    #[serde(rename = "lastUpdateId")]
+    pub last_update_id: u64,
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+}
+
+#[derive(Debug)]
+enum SyncState {
+    Buffering(Vec<DepthUpdate>),
+    Synced(u64),
+}
+
+/// The [`DepthSync`] type sequences diff depth events against a snapshot.
+#[derive(Debug)]
+pub struct DepthSync {
+    state: SyncState,
+}
+
+impl Default for DepthSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DepthSync {
+    /// Creates new [`DepthSync`] waiting for a snapshot.
+    pub fn new() -> Self {
+        Self {
+            state: SyncState::Buffering(vec![]),
+        }
+    }
+
+    /// Returns `true` if a snapshot is required to apply the buffered events.
+    pub fn needs_snapshot(&self) -> bool {
+        matches!(self.state, SyncState::Buffering(_))
+    }
+
+    /// Handles an event of the stream.
+    ///
+    /// Returns the event when it must be applied to the book and `None` when it
+    /// was buffered or is stale. A gap in the update ids returns an error and
+    /// switches back to buffering, starting with the event.
+    pub fn update(&mut self, update: DepthUpdate) -> Result<Option<DepthUpdate>> {
+        match self.state {
+            SyncState::Buffering(ref mut buffer) => {
+                buffer.push(update);
+                Ok(None)
+            }
+            SyncState::Synced(last_update_id) if update.final_update_id <= last_update_id => {
+                Ok(None)
+            }
+            SyncState::Synced(last_update_id) if update.first_update_id > last_update_id + 1 => {
+                let received = update.first_update_id;
+                self.state = SyncState::Buffering(vec![update]);
+                Err(Error::SequenceGap {
+                    expected: last_update_id + 1,
+                    received,
+                })
+            }
+            SyncState::Synced(_) => {
+                self.state = SyncState::Synced(update.final_update_id);
+                Ok(Some(update))
+            }
+        }
+    }
+
+    /// Handles a depth snapshot.
+    ///
+    /// Returns the buffered events to apply after the snapshot. An error is
+    /// returned if the buffered events do not follow the snapshot, the events
+    /// from the gap onwards are kept for the next snapshot.
+    pub fn snapshot(&mut self, snapshot: &DepthSnapshot) -> Result<Vec<DepthUpdate>> {
+        let buffer = match self.state {
+            SyncState::Buffering(ref mut buffer) => buffer,
+            SyncState::Synced(_) => return Ok(vec![]),
+        };
+
+        buffer.retain(|u| u.final_update_id > snapshot.last_update_id);
+        let mut expected = snapshot.last_update_id + 1;
+        for (index, update) in buffer.iter().enumerate() {
+            if update.first_update_id > expected {
+                let received = update.first_update_id;
+                buffer.drain(..index);
+                return Err(Error::SequenceGap { expected, received });
+            }
+            expected = update.final_update_id + 1;
+        }
+
+        let updates = std::mem::take(buffer);
+        self.state = SyncState::Synced(expected - 1);
+        Ok(updates)
+    }
+}
+
+/// The [`SnapshotBackoff`] type delays the depth snapshot fetched again after a
+/// failed attempt.
+#[derive(Debug)]
+struct SnapshotBackoff {
+    delay: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl SnapshotBackoff {
+    /// Creates new [`SnapshotBackoff`] ready to fetch a snapshot.
+    fn new() -> Self {
+        Self {
+            delay: SNAPSHOT_RETRY_DELAY,
+            retry_at: None,
+        }
+    }
+
+    /// Returns `true` if a snapshot can be fetched at the given time.
+    fn is_ready(&self, now: Instant) -> bool {
+        !matches!(self.retry_at, Some(retry_at) if now < retry_at)
+    }
+
+    /// Delays the next attempt, doubling the delay up to its maximum.
+    fn failed(&mut self, now: Instant) {
+        self.retry_at = Some(now + self.delay);
+        self.delay = (self.delay * 2).min(SNAPSHOT_RETRY_MAX_DELAY);
+    }
+
+    /// Resets the delay once a snapshot is applied.
+    fn succeeded(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// The [`BinanceAdapter`] type is the adapter of the Binance diff depth stream.
+pub struct BinanceAdapter;
+
+impl ExchangeAdapter for BinanceAdapter {
+    fn exchange(&self) -> Exchange {
+        Exchange::BINANCE
+    }
+
+    fn subscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+        Ok(request("SUBSCRIBE", &config.channel))
+    }
+
+    fn unsubscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+        Ok(request("UNSUBSCRIBE", &config.channel))
+    }
+
+    fn decoder(&self, config: &ExchangeConfig) -> Result<Box<dyn BookDecoder>> {
+        let snapshot_url = config
+            .snapshot_url
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("missing Binance depth snapshot url"))?;
+
+        Ok(Box::new(BinanceDecoder {
+            client: reqwest::Client::new(),
+            snapshot_url,
+            symbol: config.channel.to_uppercase(),
+            sync: DepthSync::new(),
+            backoff: SnapshotBackoff::new(),
+        }))
+    }
+}
+
+/// Creates a request of the diff depth stream of the channel.
+fn request(method: &str, channel: &str) -> Message {
+    Message::Text(
+        serde_json::json!({
+            "method": method,
+            "params": [format!("{}@depth", channel)],
+            "id": 1
+        })
+        .to_string(),
+    )
+}
+
+/// The [`BinanceDecoder`] type synchronises the book of a Binance connection.
+///
+/// The depth snapshot is fetched when an event is received while the book is
+/// not synchronised. Events received meanwhile wait on the socket and are
+/// sequenced once the snapshot is applied. A snapshot older than the buffered
+/// events is discarded and fetched again on an event received after the
+/// backoff delay.
+struct BinanceDecoder {
+    client: reqwest::Client,
+    snapshot_url: String,
+    symbol: String,
+    sync: DepthSync,
+    backoff: SnapshotBackoff,
+}
+
+#[async_trait]
+impl BookDecoder for BinanceDecoder {
+    #[tracing::instrument(name = "Decode Binance message", skip(self, message))]
+    async fn decode(&mut self, message: Message) -> Result<Vec<BookUpdate>> {
+        let update = match parse_update(message) {
+            Some(update) => update,
+            None => return Ok(vec![]),
+        };
+
+        match self.sync.update(update) {
+            Ok(Some(update)) => {
+                return Ok(BookUpdate::levels(
+                    &Exchange::BINANCE,
+                    update.bids,
+                    update.asks,
+                ))
+            }
+            Ok(None) => (),
+            Err(e) => tracing::warn!("resynchronising Binance book: {}", e),
+        }
+
+        if !self.sync.needs_snapshot() || !self.backoff.is_ready(Instant::now()) {
+            return Ok(vec![]);
+        }
+
+        let depth = match fetch_snapshot(&self.client, &self.snapshot_url, &self.symbol).await {
+            Ok(depth) => depth,
+            Err(e) => {
+                self.backoff.failed(Instant::now());
+                return Err(e);
+            }
+        };
+        let buffered = match self.sync.snapshot(&depth) {
+            Ok(buffered) => buffered,
+            Err(e) => {
+                tracing::warn!("discarding Binance depth snapshot: {}", e);
+                self.backoff.failed(Instant::now());
+                return Ok(vec![]);
+            }
+        };
+        self.backoff.succeeded();
+        let mut updates = vec![BookUpdate::Clear(Exchange::BINANCE)];
+        updates.extend(BookUpdate::levels(
+            &Exchange::BINANCE,
+            depth.bids,
+            depth.asks,
+        ));
+        for update in buffered {
+            updates.extend(BookUpdate::levels(
+                &Exchange::BINANCE,
+                update.bids,
+                update.asks,
+            ));
+        }
+
+        Ok(updates)
+    }
+}
+
+/// Fetches a depth snapshot of the symbol.
+async fn fetch_snapshot(
+    client: &reqwest::Client,
+    url: &str,
+    symbol: &str,
+) -> Result<DepthSnapshot> {
+    let limit = SNAPSHOT_LIMIT.to_string();
+    let snapshot = client
+        .get(url)
+        .query(&[("symbol", symbol), ("limit", limit.as_str())])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(snapshot)
+}
+
+/// Parses a depth update, other messages such as subscription replies are logged.
+fn parse_update(message: Message) -> Option<DepthUpdate> {
+    let text = match message {
+        Message::Text(text) => text,
+        _ => return None,
+    };
+
+    match BinanceEvent::parse(&text) {
+        Ok(BinanceEvent::DepthUpdate(update)) => return Some(update),
+        Ok(BinanceEvent::Response { id }) => tracing::debug!("Binance request {} succeeded", id),
+        Ok(BinanceEvent::Error { code, msg }) => tracing::error!("Binance error {}: {}", code, msg),
+        Ok(BinanceEvent::Unknown) => event::record_unknown(&Exchange::BINANCE, &text),
+        Err(e) => tracing::error!("failed to parse Binance message '{}': {}", text, e),
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(first_update_id: u64, final_update_id: u64) -> DepthUpdate {
+        DepthUpdate {
+            first_update_id,
+            final_update_id,
+            bids: vec![],
+            asks: vec![],
+        }
+    }
+
+    fn snapshot(last_update_id: u64) -> DepthSnapshot {
+        DepthSnapshot {
+            last_update_id,
+            bids: vec![],
+            asks: vec![],
+        }
+    }
+
+    #[test]
+    fn sync_drops_stale_buffered_updates() {
+        let mut sync = DepthSync::new();
+        for (first, last) in [(1, 3), (4, 6), (7, 8)] {
+            assert!(matches!(sync.update(update(first, last)), Ok(None)));
+        }
+
+        let updates = sync.snapshot(&snapshot(5)).unwrap();
+        assert_eq!(updates, vec![update(4, 6), update(7, 8)]);
+        assert!(!sync.needs_snapshot());
+        assert!(matches!(sync.update(update(5, 8)), Ok(None)));
+        assert_eq!(sync.update(update(9, 10)).unwrap(), Some(update(9, 10)));
+    }
+
+    #[test]
+    fn sync_rejects_snapshot_older_than_buffer() {
+        let mut sync = DepthSync::new();
+        sync.update(update(10, 12)).unwrap();
+
+        assert!(matches!(
+            sync.snapshot(&snapshot(5)),
+            Err(Error::SequenceGap {
+                expected: 6,
+                received: 10
+            })
+        ));
+        assert!(sync.needs_snapshot());
+        assert_eq!(sync.snapshot(&snapshot(11)).unwrap(), vec![update(10, 12)]);
+    }
+
+    #[test]
+    fn sync_rejects_gap_in_buffer() {
+        let mut sync = DepthSync::new();
+        for (first, last) in [(4, 6), (7, 8), (12, 13)] {
+            sync.update(update(first, last)).unwrap();
+        }
+
+        assert!(matches!(
+            sync.snapshot(&snapshot(5)),
+            Err(Error::SequenceGap {
+                expected: 9,
+                received: 12
+            })
+        ));
+        assert_eq!(sync.snapshot(&snapshot(11)).unwrap(), vec![update(12, 13)]);
+    }
+
+    #[test]
+    fn sync_resynchronises_on_gap() {
+        let mut sync = DepthSync::new();
+        sync.snapshot(&snapshot(5)).unwrap();
+
+        assert!(matches!(
+            sync.update(update(8, 9)),
+            Err(Error::SequenceGap {
+                expected: 6,
+                received: 8
+            })
+        ));
+        assert!(sync.needs_snapshot());
+        assert_eq!(sync.snapshot(&snapshot(8)).unwrap(), vec![update(8, 9)]);
+    }
+
+    #[test]
+    fn snapshot_retries_are_delayed() {
+        let now = Instant::now();
+        let mut backoff = SnapshotBackoff::new();
+        assert!(backoff.is_ready(now));
+
+        backoff.failed(now);
+        assert!(!backoff.is_ready(now + Duration::from_millis(499)));
+        assert!(backoff.is_ready(now + SNAPSHOT_RETRY_DELAY));
+
+        backoff.failed(now);
+        assert!(!backoff.is_ready(now + SNAPSHOT_RETRY_DELAY));
+        assert!(backoff.is_ready(now + SNAPSHOT_RETRY_DELAY * 2));
+
+        for _ in 0..10 {
+            backoff.failed(now);
+        }
+        assert!(backoff.is_ready(now + SNAPSHOT_RETRY_MAX_DELAY));
+
+        backoff.succeeded();
+        assert!(backoff.is_ready(now));
+    }
+}
@@ -0,0 +1,322 @@
+//! Bitfinex integration.
+//!
+//! This module implements the Bitfinex `book` channel adapter. The channel
+//! data is sent as positional arrays keyed by the id of the channel returned
+//! in the subscription reply. The first message of a channel is a snapshot,
+//! the following ones each update a level.
+//!
+//! Channels are unsubscribed by id, so an unsubscribe requested before the
+//! subscription reply is sent once the reply is received, and a reply for
+//! another symbol than the configured one is unsubscribed.
+//!
+//! Bitfinex sends a heartbeat on the channels without update every 15
+//! seconds, so the connection is considered lost after two missed heartbeats.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use tungstenite::Message;
+
+use super::adapter::{BookDecoder, ExchangeAdapter};
+use super::event::{self, BitfinexEvent, BitfinexLevel};
+use crate::configuration::ExchangeConfig;
+use crate::prelude::{BookKind, BookUpdate, Exchange, Level, Result};
+
+/// The Bitfinex exchange.
+pub const BITFINEX: Exchange = Exchange::from_static("bitfinex");
+
+/// Number of levels of the subscribed book.
+const BOOK_LENGTH: &str = "25";
+
+/// Longest period without message before the connection is considered lost.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The [`BitfinexAdapter`] type is the adapter of the Bitfinex book channel.
+pub struct BitfinexAdapter;
+
+impl ExchangeAdapter for BitfinexAdapter {
+    fn exchange(&self) -> Exchange {
+        BITFINEX
+    }
+
+    fn subscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+        Ok(Message::Text(
+            serde_json::json!({
+                "event": "subscribe",
+                "channel": "book",
+                "symbol": symbol(&config.channel),
+                "prec": "P0",
+                "len": BOOK_LENGTH
+            })
+            .to_string(),
+        ))
+    }
+
+    /// Bitfinex unsubscribes by the channel id of the subscription reply, so
+    /// the message is created by the decoder of the connection.
+    fn unsubscribe_message(&self, _config: &ExchangeConfig) -> Result<Message> {
+        Err(anyhow::anyhow!("Bitfinex channels are unsubscribed by channel id").into())
+    }
+
+    fn heartbeat_message(&self) -> Option<Message> {
+        Some(Message::Text(
+            serde_json::json!({"event": "ping"}).to_string(),
+        ))
+    }
+
+    fn heartbeat_timeout(&self) -> Option<Duration> {
+        Some(HEARTBEAT_TIMEOUT)
+    }
+
+    fn decoder(&self, config: &ExchangeConfig) -> Result<Box<dyn BookDecoder>> {
+        Ok(Box::new(BitfinexDecoder::new(symbol(&config.channel))))
+    }
+}
+
+/// Returns the Bitfinex trading symbol of a channel, such as `tBTCUSD` for
+/// `btcusd` or `btc/usd`.
+pub fn symbol(channel: &str) -> String {
+    format!(
+        "t{}",
+        channel.to_uppercase().replace(&['-', '/', '_'][..], "")
+    )
+}
+
+/// The [`BitfinexDecoder`] type decodes the messages of a Bitfinex connection.
+#[derive(Debug)]
+struct BitfinexDecoder {
+    /// Trading symbol of the subscribed book.
+    symbol: String,
+    /// Id of the book channel, once the subscription reply is received.
+    channel_id: Option<u64>,
+    /// Whether the channel is unsubscribed as soon as its id is known.
+    unsubscribe_pending: bool,
+    replies: Vec<Message>,
+}
+
+#[async_trait]
+impl BookDecoder for BitfinexDecoder {
+    #[tracing::instrument(name = "Decode Bitfinex message", skip(self, message))]
+    async fn decode(&mut self, message: Message) -> Result<Vec<BookUpdate>> {
+        let text = match message {
+            Message::Text(text) => text,
+            _ => return Ok(vec![]),
+        };
+
+        match BitfinexEvent::parse(&text)? {
+            BitfinexEvent::Snapshot { channel_id, levels } if self.is_book(channel_id) => {
+                let updates = std::iter::once(BookUpdate::Clear(BITFINEX))
+                    .chain(levels.into_iter().map(book_update))
+                    .collect();
+                return Ok(updates);
+            }
+            BitfinexEvent::Update { channel_id, level } if self.is_book(channel_id) => {
+                return Ok(vec![book_update(level)]);
+            }
+            BitfinexEvent::Heartbeat { channel_id } => {
+                tracing::debug!("received Bitfinex heartbeat on channel {}", channel_id)
+            }
+            BitfinexEvent::Subscribed {
+                channel_id,
+                channel,
+                symbol,
+            } if channel == "book" => {
+                if symbol.as_deref() != Some(self.symbol.as_str()) {
+                    tracing::warn!(
+                        "unsubscribing from Bitfinex book {:?} instead of {}",
+                        symbol,
+                        self.symbol
+                    );
+                    self.replies.push(unsubscribe_message(channel_id));
+                } else if std::mem::take(&mut self.unsubscribe_pending) {
+                    tracing::info!("unsubscribing from Bitfinex book {}", self.symbol);
+                    self.replies.push(unsubscribe_message(channel_id));
+                } else {
+                    tracing::info!("subscribed to Bitfinex book {}", self.symbol);
+                    self.channel_id = Some(channel_id);
+                }
+            }
+            BitfinexEvent::Unsubscribed { channel_id } => {
+                if self.channel_id == Some(channel_id) {
+                    self.channel_id = None;
+                }
+            }
+            BitfinexEvent::Info => tracing::debug!("received Bitfinex info: {}", text),
+            BitfinexEvent::Pong => tracing::debug!("received Bitfinex pong"),
+            BitfinexEvent::Error { code, msg } => {
+                tracing::error!("Bitfinex error {:?}: {}", code, msg)
+            }
+            BitfinexEvent::Snapshot { .. }
+            | BitfinexEvent::Update { .. }
+            | BitfinexEvent::Subscribed { .. }
+            | BitfinexEvent::Unknown => event::record_unknown(&BITFINEX, &text),
+        }
+
+        Ok(vec![])
+    }
+
+    fn replies(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.replies)
+    }
+
+    fn unsubscribe_messages(&mut self) -> Option<Vec<Message>> {
+        match self.channel_id.take() {
+            Some(channel_id) => Some(vec![unsubscribe_message(channel_id)]),
+            None => {
+                self.unsubscribe_pending = true;
+                Some(vec![])
+            }
+        }
+    }
+}
+
+impl BitfinexDecoder {
+    /// Creates new decoder of the book of a trading symbol.
+    fn new(symbol: String) -> Self {
+        Self {
+            symbol,
+            channel_id: None,
+            unsubscribe_pending: false,
+            replies: vec![],
+        }
+    }
+
+    /// Returns `true` if the channel is the subscribed book channel.
+    fn is_book(&self, channel_id: u64) -> bool {
+        self.channel_id == Some(channel_id)
+    }
+}
+
+/// Creates the message unsubscribing from a channel.
+fn unsubscribe_message(channel_id: u64) -> Message {
+    Message::Text(serde_json::json!({"event": "unsubscribe", "chanId": channel_id}).to_string())
+}
+
+/// Converts a Bitfinex level to a book update.
+///
+/// The side is given by the sign of the amount, and a level without orders is
+/// deleted.
+fn book_update(level: BitfinexLevel) -> BookUpdate {
+    let BitfinexLevel(price, count, amount) = level;
+    let kind = if amount.is_sign_negative() {
+        BookKind::Asks
+    } else {
+        BookKind::Bids
+    };
+    let amount = if count == 0 {
+        Decimal::ZERO
+    } else {
+        amount.abs()
+    };
+
+    BookUpdate::level(kind, Level::new(BITFINEX, price, amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    use super::*;
+
+    async fn decode(decoder: &mut BitfinexDecoder, value: serde_json::Value) -> Vec<BookUpdate> {
+        decoder
+            .decode(Message::Text(value.to_string()))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn channels_are_converted_to_symbols() {
+        assert_eq!(symbol("btcusd"), "tBTCUSD");
+        assert_eq!(symbol("eth/usd"), "tETHUSD");
+    }
+
+    #[tokio::test]
+    async fn decoder_maps_channel_ids_to_book() {
+        let mut decoder = BitfinexDecoder::new(symbol("btcusd"));
+        let level =
+            |kind, price, amount| BookUpdate::level(kind, Level::new(BITFINEX, price, amount));
+
+        assert_eq!(
+            decode(&mut decoder, json!([42, [[100.5, 1, 2]]])).await,
+            vec![]
+        );
+        decode(
+            &mut decoder,
+            json!({"event": "subscribed", "channel": "book", "chanId": 42, "symbol": "tBTCUSD"}),
+        )
+        .await;
+
+        assert_eq!(
+            decode(&mut decoder, json!([42, [[100.5, 1, 2], [101, 2, -1.5]]])).await,
+            vec![
+                BookUpdate::Clear(BITFINEX),
+                level(BookKind::Bids, dec!(100.5), dec!(2)),
+                level(BookKind::Asks, dec!(101), dec!(1.5)),
+            ]
+        );
+        assert_eq!(decode(&mut decoder, json!([42, "hb"])).await, vec![]);
+        assert_eq!(
+            decode(&mut decoder, json!([42, [101, 0, -1]])).await,
+            vec![level(BookKind::Asks, dec!(101), dec!(0))]
+        );
+        assert_eq!(
+            decode(&mut decoder, json!([42, [100.5, 0, 1]])).await,
+            vec![BookUpdate::Delete(
+                BookKind::Bids,
+                Level::new(BITFINEX, dec!(100.5), dec!(0))
+            )]
+        );
+
+        assert_eq!(
+            decoder.unsubscribe_messages(),
+            Some(vec![unsubscribe_message(42)])
+        );
+        assert_eq!(
+            decode(&mut decoder, json!([42, [101, 1, -1]])).await,
+            vec![]
+        );
+        decode(
+            &mut decoder,
+            json!({"event": "unsubscribed", "status": "OK", "chanId": 42}),
+        )
+        .await;
+        assert_eq!(decoder.replies(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn decoder_unsubscribes_other_symbols() {
+        let mut decoder = BitfinexDecoder::new(symbol("btcusd"));
+
+        decode(
+            &mut decoder,
+            json!({"event": "subscribed", "channel": "book", "chanId": 7, "symbol": "tETHUSD"}),
+        )
+        .await;
+        assert_eq!(decoder.replies(), vec![unsubscribe_message(7)]);
+        assert_eq!(
+            decode(&mut decoder, json!([7, [[100.5, 1, 2]]])).await,
+            vec![]
+        );
+    }
+
+    #[tokio::test]
+    async fn decoder_unsubscribes_once_subscription_is_replied() {
+        let mut decoder = BitfinexDecoder::new(symbol("btcusd"));
+
+        assert_eq!(decoder.unsubscribe_messages(), Some(vec![]));
+        decode(
+            &mut decoder,
+            json!({"event": "subscribed", "channel": "book", "chanId": 42, "symbol": "tBTCUSD"}),
+        )
+        .await;
+        assert_eq!(decoder.replies(), vec![unsubscribe_message(42)]);
+        assert_eq!(
+            decode(&mut decoder, json!([42, [[100.5, 1, 2]]])).await,
+            vec![]
+        );
+        assert_eq!(decoder.replies(), vec![]);
+    }
+}
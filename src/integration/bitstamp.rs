@@ -0,0 +1,168 @@
+//! Bitstamp integration.
+//!
+//! This module implements the Bitstamp order book adapter. Each `data` message
+//! of the `order_book_<symbol>` channel is a snapshot of the top levels, so the
+//! Bitstamp book is cleared before the levels are applied.
+
+use async_trait::async_trait;
+use tungstenite::Message;
+
+use super::adapter::{BookDecoder, ExchangeAdapter};
+use super::event::{self, BitstampEvent};
+use crate::configuration::ExchangeConfig;
+use crate::prelude::{BookUpdate, Exchange, Result};
+
+/// The [`BitstampAdapter`] type is the adapter of the Bitstamp order book channel.
+pub struct BitstampAdapter;
+
+impl ExchangeAdapter for BitstampAdapter {
+    fn exchange(&self) -> Exchange {
+        Exchange::BITSTAMP
+    }
+
+    fn subscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+        Ok(request("bts:subscribe", &config.channel))
+    }
+
+    fn unsubscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+        Ok(request("bts:unsubscribe", &config.channel))
+    }
+
+    fn heartbeat_message(&self) -> Option<Message> {
+        Some(Message::Text(
+            serde_json::json!({"event": "bts:heartbeat"}).to_string(),
+        ))
+    }
+
+    fn decoder(&self, _config: &ExchangeConfig) -> Result<Box<dyn BookDecoder>> {
+        Ok(Box::new(BitstampDecoder))
+    }
+}
+
+/// Creates a request of the order book channel.
+fn request(event: &str, channel: &str) -> Message {
+    Message::Text(
+        serde_json::json!({
+            "event": event,
+            "data": {
+                "channel": format!("order_book_{}", channel)
+            }
+        })
+        .to_string(),
+    )
+}
+
+/// The [`BitstampDecoder`] type decodes the messages of a Bitstamp connection.
+struct BitstampDecoder;
+
+#[async_trait]
+impl BookDecoder for BitstampDecoder {
+    /// Decodes the levels of a Bitstamp message.
+    ///
+    /// The message is rejected, and no level is decoded, if it does not match
+    /// the Bitstamp schema or contains an invalid price or amount.
+    #[tracing::instrument(name = "Decode Bitstamp message", skip(self, message))]
+    async fn decode(&mut self, message: Message) -> Result<Vec<BookUpdate>> {
+        let text = match message {
+            Message::Text(text) => text,
+            _ => return Ok(vec![]),
+        };
+
+        match BitstampEvent::parse(&text)? {
+            BitstampEvent::Data { data, .. } => {
+                let exchange = Exchange::BITSTAMP;
+                let updates = std::iter::once(BookUpdate::Clear(exchange.clone()))
+                    .chain(BookUpdate::levels(&exchange, data.bids, data.asks))
+                    .collect();
+
+                return Ok(updates);
+            }
+            BitstampEvent::Subscribed { channel } => {
+                tracing::info!("subscribed to Bitstamp channel '{}'", channel)
+            }
+            BitstampEvent::Unsubscribed { channel } => {
+                tracing::info!("unsubscribed from Bitstamp channel '{}'", channel)
+            }
+            BitstampEvent::Heartbeat => tracing::debug!("received Bitstamp heartbeat"),
+            BitstampEvent::RequestReconnect => {
+                tracing::warn!("Bitstamp requested a reconnection")
+            }
+            BitstampEvent::Error { data } => {
+                tracing::error!("Bitstamp error {:?}: {}", data.code, data.message)
+            }
+            BitstampEvent::Unknown => event::record_unknown(&Exchange::BITSTAMP, &text),
+        }
+
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{BookKind, Error};
+    use crate::telemetry::tests::force_lazy;
+    use fake;
+
+    fn generate_message_data() -> String {
+        force_lazy();
+        let gen = || -> Vec<(String, String)> {
+            fake::vec![f64; 10]
+                .iter()
+                .map(ToString::to_string)
+                .zip(fake::vec![f64; 10].iter().map(ToString::to_string))
+                .collect()
+        };
+
+        serde_json::json!({
+            "event": "data",
+            "channel": "order_book_btcusd",
+            "data": {
+                "asks": gen(),
+                "bids": gen(),
+            },
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn decode_bitstamp_successfully() {
+        let data = generate_message_data();
+        let updates = BitstampDecoder
+            .decode(Message::Text(data))
+            .await
+            .expect("failed to decode books");
+        assert_eq!(updates[0], BookUpdate::Clear(Exchange::BITSTAMP));
+        assert!(matches!(
+            updates[1],
+            BookUpdate::Level(BookKind::Bids, _) | BookUpdate::Delete(BookKind::Bids, _)
+        ));
+    }
+
+    #[tokio::test]
+    async fn decode_rejects_invalid_price() {
+        let data = serde_json::json!({
+            "event": "data",
+            "channel": "order_book_btcusd",
+            "data": {"bids": [["10.1", "1"]], "asks": [["not a price", "1"]]},
+        });
+        assert!(matches!(
+            BitstampDecoder
+                .decode(Message::Text(data.to_string()))
+                .await,
+            Err(Error::ParseError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn decode_counts_unknown_messages() {
+        let data =
+            serde_json::json!({"event": "trade", "channel": "live_trades_btcusd", "data": {}});
+        let count = event::unknown_events(&Exchange::BITSTAMP);
+        let updates = BitstampDecoder
+            .decode(Message::Text(data.to_string()))
+            .await;
+        assert_eq!(updates.unwrap(), vec![]);
+        assert!(event::unknown_events(&Exchange::BITSTAMP) > count);
+    }
+}
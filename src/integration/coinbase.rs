@@ -0,0 +1,308 @@
+//! Coinbase integration.
+//!
+//! This module implements the Coinbase Exchange `level2_batch` channel
+//! adapter, which unlike `level2` needs no authentication. The channel sends a
+//! snapshot of the book after the subscription, then `l2update` messages with
+//! the levels that changed. Updates received before the snapshot are ignored.
+//!
+//! The level2 messages carry no sequence number, so the `heartbeat` channel is
+//! subscribed too. Its heartbeats carry the sequence of the product every
+//! second: a book that is not updated while the sequence keeps moving missed
+//! its updates, and the channel is subscribed again to receive a new snapshot.
+
+use async_trait::async_trait;
+use tungstenite::Message;
+
+use super::adapter::{BookDecoder, ExchangeAdapter};
+use super::event::{self, CoinbaseEvent, CoinbaseSide};
+use crate::configuration::ExchangeConfig;
+use crate::prelude::{BookKind, BookUpdate, Error, Exchange, Level, Result};
+
+/// The Coinbase exchange.
+pub const COINBASE: Exchange = Exchange::from_static("coinbase");
+
+/// Number of heartbeats the product sequence moves without book update before
+/// the book is considered stale.
+const STALE_HEARTBEATS: u32 = 5;
+
+/// The [`CoinbaseAdapter`] type is the adapter of the Coinbase level2_batch
+/// channel.
+pub struct CoinbaseAdapter;
+
+impl ExchangeAdapter for CoinbaseAdapter {
+    fn exchange(&self) -> Exchange {
+        COINBASE
+    }
+
+    fn subscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+        Ok(request("subscribe", &config.channel))
+    }
+
+    fn unsubscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+        Ok(request("unsubscribe", &config.channel))
+    }
+
+    fn decoder(&self, config: &ExchangeConfig) -> Result<Box<dyn BookDecoder>> {
+        Ok(Box::new(CoinbaseDecoder::new(symbol(&config.channel))))
+    }
+}
+
+/// Creates a request of the level2_batch and heartbeat channels of the
+/// configured product.
+fn request(kind: &str, channel: &str) -> Message {
+    Message::Text(
+        serde_json::json!({
+            "type": kind,
+            "product_ids": [product_id(channel)],
+            "channels": ["level2_batch", "heartbeat"]
+        })
+        .to_string(),
+    )
+}
+
+/// Returns the Coinbase product id of a channel, such as `BTC-USD` for
+/// `btc-usd` or `btc/usd`.
+pub fn product_id(channel: &str) -> String {
+    channel.to_uppercase().replace(&['/', '_'][..], "-")
+}
+
+/// Returns the symbol of a Coinbase product id, such as `btcusd` for `BTC-USD`.
+pub fn symbol(product_id: &str) -> String {
+    product_id.to_lowercase().replace(&['-', '/', '_'][..], "")
+}
+
+/// The [`CoinbaseDecoder`] type decodes the messages of a Coinbase connection.
+#[derive(Debug)]
+struct CoinbaseDecoder {
+    symbol: String,
+    synced: bool,
+    /// Sequence of the product at the last heartbeat.
+    sequence: Option<u64>,
+    /// Number of heartbeats the sequence moved since the last book update.
+    stale_heartbeats: u32,
+}
+
+#[async_trait]
+impl BookDecoder for CoinbaseDecoder {
+    #[tracing::instrument(name = "Decode Coinbase message", skip(self, message))]
+    async fn decode(&mut self, message: Message) -> Result<Vec<BookUpdate>> {
+        let text = match message {
+            Message::Text(text) => text,
+            _ => return Ok(vec![]),
+        };
+
+        match CoinbaseEvent::parse(&text)? {
+            CoinbaseEvent::Snapshot {
+                product_id,
+                bids,
+                asks,
+            } if symbol(&product_id) == self.symbol => {
+                self.synced = true;
+                self.stale_heartbeats = 0;
+
+                let updates = std::iter::once(BookUpdate::Clear(COINBASE))
+                    .chain(BookUpdate::levels(&COINBASE, bids, asks))
+                    .collect();
+                return Ok(updates);
+            }
+            CoinbaseEvent::L2Update {
+                product_id,
+                changes,
+            } if symbol(&product_id) == self.symbol => {
+                if !self.synced {
+                    return Ok(vec![]);
+                }
+                self.stale_heartbeats = 0;
+
+                let updates = changes
+                    .into_iter()
+                    .map(|(side, price, amount)| {
+                        let kind = match side {
+                            CoinbaseSide::Buy => BookKind::Bids,
+                            CoinbaseSide::Sell => BookKind::Asks,
+                        };
+                        BookUpdate::level(kind, Level::new(COINBASE, price, amount))
+                    })
+                    .collect();
+                return Ok(updates);
+            }
+            CoinbaseEvent::Heartbeat {
+                product_id,
+                sequence,
+            } if symbol(&product_id) == self.symbol => {
+                tracing::debug!("received Coinbase heartbeat {}", sequence);
+                self.check_heartbeat(sequence)?;
+            }
+            CoinbaseEvent::Snapshot { product_id, .. }
+            | CoinbaseEvent::L2Update { product_id, .. }
+            | CoinbaseEvent::Heartbeat { product_id, .. } => {
+                tracing::debug!("ignoring Coinbase product '{}'", product_id)
+            }
+            CoinbaseEvent::Subscriptions => tracing::info!("subscribed to Coinbase level2_batch"),
+            CoinbaseEvent::Error { message, reason } => {
+                tracing::error!("Coinbase error: {} {:?}", message, reason)
+            }
+            CoinbaseEvent::Unknown => event::record_unknown(&COINBASE, &text),
+        }
+
+        Ok(vec![])
+    }
+}
+
+impl CoinbaseDecoder {
+    /// Creates new decoder of the book of a product symbol.
+    fn new(symbol: String) -> Self {
+        Self {
+            symbol,
+            synced: false,
+            sequence: None,
+            stale_heartbeats: 0,
+        }
+    }
+
+    /// Checks the book was updated while the product sequence moved.
+    ///
+    /// Returns a stale book [`Error`] once the sequence moved over
+    /// [`STALE_HEARTBEATS`] heartbeats without book update, so the channel is
+    /// subscribed again and the book waits for the new snapshot.
+    fn check_heartbeat(&mut self, sequence: u64) -> Result<()> {
+        let moved = matches!(self.sequence, Some(previous) if sequence > previous);
+        self.sequence = Some(sequence);
+        if !self.synced || !moved {
+            return Ok(());
+        }
+
+        self.stale_heartbeats += 1;
+        if self.stale_heartbeats < STALE_HEARTBEATS {
+            return Ok(());
+        }
+        self.synced = false;
+        self.stale_heartbeats = 0;
+        Err(Error::StaleBook(STALE_HEARTBEATS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    use super::*;
+
+    async fn decode(
+        decoder: &mut CoinbaseDecoder,
+        value: serde_json::Value,
+    ) -> Result<Vec<BookUpdate>> {
+        decoder.decode(Message::Text(value.to_string())).await
+    }
+
+    fn heartbeat(sequence: u64) -> serde_json::Value {
+        json!({
+            "type": "heartbeat",
+            "sequence": sequence,
+            "last_trade_id": 20,
+            "product_id": "BTC-USD",
+            "time": "2014-11-07T08:19:28.464459Z",
+        })
+    }
+
+    #[test]
+    fn product_ids_are_normalized_to_symbols() {
+        assert_eq!(product_id("btc-usd"), "BTC-USD");
+        assert_eq!(product_id("eth/eur"), "ETH-EUR");
+        assert_eq!(symbol("BTC-USD"), "btcusd");
+    }
+
+    #[tokio::test]
+    async fn decoder_applies_updates_after_snapshot() {
+        let mut decoder = CoinbaseDecoder::new(symbol("btc-usd"));
+
+        // Payloads of the level2 channel documentation of Coinbase Exchange.
+        let snapshot = json!({
+            "type": "snapshot",
+            "product_id": "BTC-USD",
+            "bids": [["10101.10", "0.45054140"]],
+            "asks": [["10102.55", "0.57753524"]],
+        });
+        let update = json!({
+            "type": "l2update",
+            "product_id": "BTC-USD",
+            "time": "2019-08-14T20:42:27.265Z",
+            "changes": [["buy", "10101.80000000", "0.162567"]],
+        });
+
+        assert_eq!(decode(&mut decoder, update.clone()).await.unwrap(), vec![]);
+        assert_eq!(
+            decode(&mut decoder, snapshot).await.unwrap(),
+            vec![
+                BookUpdate::Clear(COINBASE),
+                BookUpdate::Level(
+                    BookKind::Bids,
+                    Level::new(COINBASE, dec!(10101.10), dec!(0.45054140))
+                ),
+                BookUpdate::Level(
+                    BookKind::Asks,
+                    Level::new(COINBASE, dec!(10102.55), dec!(0.57753524))
+                ),
+            ]
+        );
+        assert_eq!(
+            decode(&mut decoder, update).await.unwrap(),
+            vec![BookUpdate::Level(
+                BookKind::Bids,
+                Level::new(COINBASE, dec!(10101.80000000), dec!(0.162567))
+            )]
+        );
+
+        let other = json!({
+            "type": "l2update",
+            "product_id": "ETH-USD",
+            "time": "2019-08-14T20:42:27.265Z",
+            "changes": [["sell", "200.00", "1.0"]],
+        });
+        assert_eq!(decode(&mut decoder, other).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn decoder_detects_book_stale_while_sequence_moves() {
+        let mut decoder = CoinbaseDecoder::new(symbol("btc-usd"));
+        let snapshot = json!({
+            "type": "snapshot",
+            "product_id": "BTC-USD",
+            "bids": [["10101.10", "0.45054140"]],
+            "asks": [["10102.55", "0.57753524"]],
+        });
+        let update = json!({
+            "type": "l2update",
+            "product_id": "BTC-USD",
+            "time": "2019-08-14T20:42:27.265Z",
+            "changes": [["buy", "10101.80000000", "0.162567"]],
+        });
+
+        // Heartbeats before the snapshot and of a quiet book are not stale.
+        for sequence in [1, 2, 3, 4, 5, 6] {
+            decode(&mut decoder, heartbeat(sequence)).await.unwrap();
+        }
+        decode(&mut decoder, snapshot.clone()).await.unwrap();
+        for _ in 0..10 {
+            decode(&mut decoder, heartbeat(6)).await.unwrap();
+        }
+
+        // The book updated in between heartbeats is not stale either.
+        for sequence in 7..=10 {
+            decode(&mut decoder, heartbeat(sequence)).await.unwrap();
+        }
+        decode(&mut decoder, update.clone()).await.unwrap();
+        for sequence in 11..=14 {
+            decode(&mut decoder, heartbeat(sequence)).await.unwrap();
+        }
+
+        assert!(matches!(
+            decode(&mut decoder, heartbeat(15)).await,
+            Err(Error::StaleBook(STALE_HEARTBEATS))
+        ));
+        assert_eq!(decode(&mut decoder, update).await.unwrap(), vec![]);
+        decode(&mut decoder, heartbeat(16)).await.unwrap();
+        assert_eq!(decode(&mut decoder, snapshot).await.unwrap().len(), 3);
+    }
+}
@@ -1,17 +1,830 @@
+//! Exchange event types.
+//!
+//! This module defines the schema of the messages sent by each exchange.
+//! Messages are decoded against the schema of the exchange they were received
+//! from, and messages of an unknown type are logged and counted.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use rust_decimal::Decimal;
 use serde::Deserialize;
 
-#[non_exhaustive]
-#[derive(Debug, serde::Deserialize)]
+use super::binance::DepthUpdate;
+use crate::prelude::{Exchange, Result};
+
+/// Number of messages of an unknown type received per exchange.
+static UNKNOWN_EVENTS: Lazy<Mutex<HashMap<Exchange, u64>>> = Lazy::new(Default::default);
+
+/// Records a message of an unknown type received from an exchange.
+pub fn record_unknown(exchange: &Exchange, text: &str) {
+    tracing::warn!("unknown message from {}: {}", exchange.as_ref(), text);
+    if let Ok(mut counts) = UNKNOWN_EVENTS.lock() {
+        *counts.entry(exchange.clone()).or_default() += 1;
+    }
+}
+
+/// Returns the number of messages of an unknown type received from an exchange.
+pub fn unknown_events(exchange: &Exchange) -> u64 {
+    UNKNOWN_EVENTS
+        .lock()
+        .map(|counts| counts.get(exchange).copied().unwrap_or_default())
+        .unwrap_or_default()
+}
+
+/// The [`BinanceEvent`] type is a message of the Binance stream.
+#[derive(Debug, PartialEq)]
+pub enum BinanceEvent {
+    /// Levels changed since the previous depth update.
+    DepthUpdate(DepthUpdate),
+
+    /// Reply to a request such as `SUBSCRIBE`.
+    Response { id: u64 },
+
+    /// Error reply to a request.
+    Error { code: i64, msg: String },
+
+    /// Message of an unknown type.
+    Unknown,
+}
+
+#[derive(Debug, Deserialize)]
+struct BinanceError {
+    code: i64,
+    msg: String,
+}
+
+#[derive(Debug, Deserialize)]
 #[serde(untagged)]
-pub enum Event {
-    Binance(EventData),
-    Bitstamp { data: EventData },
+enum BinanceMessage {
+    Event {
+        #[serde(rename = "e")]
+        event: String,
+    },
+    Error {
+        error: BinanceError,
+    },
+    Response {
+        id: u64,
+    },
+}
+
+impl BinanceEvent {
+    /// Parses a Binance message.
+    ///
+    /// Events are identified by their `e` field, the other messages are
+    /// replies to requests.
+    pub fn parse(text: &str) -> Result<Self> {
+        let event = match serde_json::from_str::<BinanceMessage>(text) {
+            Ok(BinanceMessage::Event { event }) if event == "depthUpdate" => {
+                Self::DepthUpdate(serde_json::from_str(text)?)
+            }
+            Ok(BinanceMessage::Event { .. }) | Err(_) => Self::Unknown,
+            Ok(BinanceMessage::Error { error }) => Self::Error {
+                code: error.code,
+                msg: error.msg,
+            },
+            Ok(BinanceMessage::Response { id }) => Self::Response { id },
+        };
+
+        Ok(event)
+    }
+}
+
+/// The [`BitstampEvent`] type is a message of the Bitstamp stream.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "event")]
+pub enum BitstampEvent {
+    /// Snapshot of the top levels of the order book.
+    #[serde(rename = "data")]
+    Data { channel: String, data: BitstampBook },
+
+    #[serde(rename = "bts:subscription_succeeded")]
+    Subscribed { channel: String },
+
+    #[serde(rename = "bts:unsubscription_succeeded")]
+    Unsubscribed { channel: String },
+
+    /// Reply to a `bts:heartbeat` request.
+    #[serde(rename = "bts:heartbeat")]
+    Heartbeat,
+
+    /// Request to reconnect before the server goes into maintenance.
+    #[serde(rename = "bts:request_reconnect")]
+    RequestReconnect,
+
+    #[serde(rename = "bts:error")]
+    Error { data: BitstampError },
+
+    /// Message of an unknown type.
+    #[serde(other)]
+    Unknown,
+}
+
+/// The [`BitstampBook`] type is the order book data of a Bitstamp message.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct BitstampBook {
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+}
+
+/// The [`BitstampError`] type is the error data of a Bitstamp message.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct BitstampError {
+    pub code: Option<i64>,
+    pub message: String,
+}
+
+impl BitstampEvent {
+    /// Parses a Bitstamp message.
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+}
+
+/// The [`KrakenEvent`] type is a message of the Kraken stream.
+///
+/// Channel data is framed as an array of the channel id, one or two payload
+/// objects, the channel name and the pair. Other messages are objects
+/// identified by their `event` field.
+#[derive(Debug, PartialEq)]
+pub enum KrakenEvent {
+    /// Snapshot of the top levels of the order book.
+    Snapshot {
+        pair: String,
+        bids: Vec<KrakenLevel>,
+        asks: Vec<KrakenLevel>,
+    },
+
+    /// Levels changed since the previous message, with the checksum of the
+    /// top levels of the book once they are applied.
+    Update {
+        pair: String,
+        bids: Vec<KrakenLevel>,
+        asks: Vec<KrakenLevel>,
+        checksum: Option<u32>,
+    },
+
+    Heartbeat,
+
+    /// Reply to a `ping` request.
+    Pong,
+
+    /// Reply to a `subscribe` or `unsubscribe` request.
+    SubscriptionStatus {
+        status: String,
+        pair: Option<String>,
+        error_message: Option<String>,
+    },
+
+    SystemStatus {
+        status: String,
+    },
+
+    /// Message of an unknown type.
+    Unknown,
+}
+
+/// The [`KrakenLevel`] type is a price level of a Kraken book message.
+///
+/// The fourth element is only sent for levels republished after a change of
+/// their position in the book.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct KrakenLevel(
+    pub Decimal,
+    pub Decimal,
+    pub String,
+    #[serde(default)] pub Option<String>,
+);
+
+impl KrakenLevel {
+    /// Returns the price and volume of the level.
+    pub fn price_amount(&self) -> (Decimal, Decimal) {
+        (self.0, self.1)
+    }
 }
 
 #[derive(Debug, Deserialize)]
-pub struct EventData {
-    #[serde(alias = "a")]
-    pub bids: Vec<(String, String)>,
-    #[serde(alias = "b")]
-    pub asks: Vec<(String, String)>,
+#[serde(tag = "event")]
+enum KrakenMessage {
+    #[serde(rename = "heartbeat")]
+    Heartbeat,
+
+    #[serde(rename = "pong")]
+    Pong,
+
+    #[serde(rename = "subscriptionStatus")]
+    SubscriptionStatus {
+        status: String,
+        pair: Option<String>,
+        #[serde(rename = "errorMessage")]
+        error_message: Option<String>,
+    },
+
+    #[serde(rename = "systemStatus")]
+    SystemStatus { status: String },
+
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct KrakenBook {
+    #[serde(rename = "bs")]
+    snapshot_bids: Option<Vec<KrakenLevel>>,
+    #[serde(rename = "as")]
+    snapshot_asks: Option<Vec<KrakenLevel>>,
+    #[serde(rename = "b", default)]
+    bids: Vec<KrakenLevel>,
+    #[serde(rename = "a", default)]
+    asks: Vec<KrakenLevel>,
+    #[serde(rename = "c")]
+    checksum: Option<String>,
+}
+
+impl KrakenEvent {
+    /// Parses a Kraken message.
+    ///
+    /// Channel data of a channel other than `book` is unknown.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut frame = match serde_json::from_str(text)? {
+            serde_json::Value::Array(frame) => frame,
+            message => {
+                let event = match serde_json::from_value(message)? {
+                    KrakenMessage::Heartbeat => Self::Heartbeat,
+                    KrakenMessage::Pong => Self::Pong,
+                    KrakenMessage::SubscriptionStatus {
+                        status,
+                        pair,
+                        error_message,
+                    } => Self::SubscriptionStatus {
+                        status,
+                        pair,
+                        error_message,
+                    },
+                    KrakenMessage::SystemStatus { status } => Self::SystemStatus { status },
+                    KrakenMessage::Unknown => Self::Unknown,
+                };
+                return Ok(event);
+            }
+        };
+
+        if frame.len() < 4 {
+            return Ok(Self::Unknown);
+        }
+        let pair = serde_json::from_value::<String>(frame.pop().unwrap_or_default())?;
+        let channel = serde_json::from_value::<String>(frame.pop().unwrap_or_default())?;
+        if !channel.starts_with("book") {
+            return Ok(Self::Unknown);
+        }
+
+        let mut book = KrakenBook::default();
+        for payload in frame.into_iter().skip(1) {
+            let payload = serde_json::from_value::<KrakenBook>(payload)?;
+            book.snapshot_bids = book.snapshot_bids.or(payload.snapshot_bids);
+            book.snapshot_asks = book.snapshot_asks.or(payload.snapshot_asks);
+            book.bids.extend(payload.bids);
+            book.asks.extend(payload.asks);
+            book.checksum = book.checksum.or(payload.checksum);
+        }
+
+        let event = match (book.snapshot_bids, book.snapshot_asks) {
+            (None, None) => Self::Update {
+                pair,
+                bids: book.bids,
+                asks: book.asks,
+                checksum: book
+                    .checksum
+                    .map(|c| c.parse())
+                    .transpose()
+                    .map_err(|e| anyhow::anyhow!("invalid Kraken checksum: {}", e))?,
+            },
+            (bids, asks) => Self::Snapshot {
+                pair,
+                bids: bids.unwrap_or_default(),
+                asks: asks.unwrap_or_default(),
+            },
+        };
+
+        Ok(event)
+    }
+}
+
+/// The [`CoinbaseEvent`] type is a message of the Coinbase stream.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum CoinbaseEvent {
+    /// Snapshot of the order book.
+    #[serde(rename = "snapshot")]
+    Snapshot {
+        product_id: String,
+        bids: Vec<(Decimal, Decimal)>,
+        asks: Vec<(Decimal, Decimal)>,
+    },
+
+    /// Levels changed since the previous message.
+    #[serde(rename = "l2update")]
+    L2Update {
+        product_id: String,
+        changes: Vec<(CoinbaseSide, Decimal, Decimal)>,
+    },
+
+    /// Reply to a `subscribe` or `unsubscribe` request.
+    #[serde(rename = "subscriptions")]
+    Subscriptions,
+
+    /// Heartbeat of the `heartbeat` channel, sent every second with the
+    /// sequence of the last message of the product.
+    #[serde(rename = "heartbeat")]
+    Heartbeat { product_id: String, sequence: u64 },
+
+    #[serde(rename = "error")]
+    Error {
+        message: String,
+        reason: Option<String>,
+    },
+
+    /// Message of an unknown type.
+    #[serde(other)]
+    Unknown,
+}
+
+/// The [`CoinbaseSide`] type is the side of a changed Coinbase level.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CoinbaseSide {
+    Buy,
+    Sell,
+}
+
+impl CoinbaseEvent {
+    /// Parses a Coinbase message.
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+}
+
+/// The [`BitfinexEvent`] type is a message of the Bitfinex stream.
+///
+/// Channel data is framed as an array of the channel id and a payload, which
+/// is `"hb"` for heartbeats. Other messages are objects identified by their
+/// `event` field.
+#[derive(Debug, PartialEq)]
+pub enum BitfinexEvent {
+    /// Snapshot of the order book of a channel.
+    Snapshot {
+        channel_id: u64,
+        levels: Vec<BitfinexLevel>,
+    },
+
+    /// Level changed since the previous message of a channel.
+    Update {
+        channel_id: u64,
+        level: BitfinexLevel,
+    },
+
+    Heartbeat {
+        channel_id: u64,
+    },
+
+    /// Reply to a `ping` request.
+    Pong,
+
+    /// Reply to a `subscribe` request.
+    Subscribed {
+        channel_id: u64,
+        channel: String,
+        symbol: Option<String>,
+    },
+
+    /// Reply to an `unsubscribe` request.
+    Unsubscribed {
+        channel_id: u64,
+    },
+
+    Info,
+
+    Error {
+        code: Option<i64>,
+        msg: String,
+    },
+
+    /// Message of an unknown type.
+    Unknown,
+}
+
+/// The [`BitfinexLevel`] type is the price, number of orders and amount of a
+/// Bitfinex price level.
+///
+/// Asks have a negative amount, and a level without orders is deleted.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct BitfinexLevel(pub Decimal, pub u64, pub Decimal);
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "event")]
+enum BitfinexMessage {
+    #[serde(rename = "subscribed")]
+    Subscribed {
+        #[serde(rename = "chanId")]
+        channel_id: u64,
+        channel: String,
+        symbol: Option<String>,
+    },
+
+    #[serde(rename = "unsubscribed")]
+    Unsubscribed {
+        #[serde(rename = "chanId")]
+        channel_id: u64,
+    },
+
+    #[serde(rename = "info")]
+    Info,
+
+    #[serde(rename = "pong")]
+    Pong,
+
+    #[serde(rename = "error")]
+    Error { code: Option<i64>, msg: String },
+
+    #[serde(other)]
+    Unknown,
+}
+
+impl BitfinexEvent {
+    /// Parses a Bitfinex message.
+    pub fn parse(text: &str) -> Result<Self> {
+        let frame = match serde_json::from_str(text)? {
+            serde_json::Value::Array(frame) => frame,
+            message => {
+                let event = match serde_json::from_value(message)? {
+                    BitfinexMessage::Subscribed {
+                        channel_id,
+                        channel,
+                        symbol,
+                    } => Self::Subscribed {
+                        channel_id,
+                        channel,
+                        symbol,
+                    },
+                    BitfinexMessage::Unsubscribed { channel_id } => {
+                        Self::Unsubscribed { channel_id }
+                    }
+                    BitfinexMessage::Info => Self::Info,
+                    BitfinexMessage::Pong => Self::Pong,
+                    BitfinexMessage::Error { code, msg } => Self::Error { code, msg },
+                    BitfinexMessage::Unknown => Self::Unknown,
+                };
+                return Ok(event);
+            }
+        };
+
+        let (channel_id, payload) = match frame.as_slice() {
+            [channel_id, payload, ..] => (channel_id, payload),
+            _ => return Ok(Self::Unknown),
+        };
+        let channel_id = match channel_id.as_u64() {
+            Some(channel_id) => channel_id,
+            None => return Ok(Self::Unknown),
+        };
+
+        let event = match payload {
+            serde_json::Value::String(payload) if payload == "hb" => Self::Heartbeat { channel_id },
+            serde_json::Value::Array(levels) if levels.iter().all(|l| l.is_array()) => {
+                Self::Snapshot {
+                    channel_id,
+                    levels: serde_json::from_value(payload.clone())?,
+                }
+            }
+            serde_json::Value::Array(_) => Self::Update {
+                channel_id,
+                level: serde_json::from_value(payload.clone())?,
+            },
+            _ => Self::Unknown,
+        };
+
+        Ok(event)
+    }
+}
+
+/// The [`HuobiEvent`] type is a message of the Huobi stream.
+#[derive(Debug, PartialEq)]
+pub enum HuobiEvent {
+    /// Application level ping, to be answered with a pong of the same value.
+    Ping(u64),
+
+    /// Snapshot of the top levels of the order book.
+    Depth(HuobiBook),
+
+    /// Reply to a `sub` or `unsub` request.
+    Response {
+        status: String,
+        message: Option<String>,
+    },
+
+    /// Message of an unknown type.
+    Unknown,
+}
+
+/// The [`HuobiBook`] type is the order book data of a Huobi message.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct HuobiBook {
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HuobiMessage {
+    ping: Option<u64>,
+    #[serde(rename = "ch")]
+    channel: Option<String>,
+    status: Option<String>,
+    #[serde(rename = "err-msg")]
+    message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HuobiDepth {
+    tick: HuobiBook,
+}
+
+impl HuobiEvent {
+    /// Parses a Huobi message.
+    ///
+    /// Channel data is identified by its `ch` field, and only the data of the
+    /// depth channels is decoded.
+    pub fn parse(text: &str) -> Result<Self> {
+        let message = serde_json::from_str::<HuobiMessage>(text)?;
+        let event = match message {
+            HuobiMessage {
+                ping: Some(ping), ..
+            } => Self::Ping(ping),
+            HuobiMessage {
+                channel: Some(channel),
+                ..
+            } if channel.contains(".depth.") => {
+                Self::Depth(serde_json::from_str::<HuobiDepth>(text)?.tick)
+            }
+            HuobiMessage {
+                status: Some(status),
+                message,
+                ..
+            } => Self::Response { status, message },
+            _ => Self::Unknown,
+        };
+
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    use super::*;
+    use crate::prelude::Error;
+
+    #[test]
+    fn binance_depth_update_maps_bids_and_asks() {
+        let text = json!({
+            "e": "depthUpdate", "E": 1, "s": "BTCUSDT", "U": 1, "u": 2,
+            "b": [["100.0", "1.5"]],
+            "a": [["101.0", "2.5"]],
+        });
+
+        let event = BinanceEvent::parse(&text.to_string()).unwrap();
+        assert_eq!(
+            event,
+            BinanceEvent::DepthUpdate(DepthUpdate {
+                first_update_id: 1,
+                final_update_id: 2,
+                bids: vec![(dec!(100.0), dec!(1.5))],
+                asks: vec![(dec!(101.0), dec!(2.5))],
+            })
+        );
+    }
+
+    #[test]
+    fn binance_replies_and_unknown_events_are_not_depth_updates() {
+        let parse = |value: serde_json::Value| BinanceEvent::parse(&value.to_string()).unwrap();
+
+        assert_eq!(
+            parse(json!({"result": null, "id": 1})),
+            BinanceEvent::Response { id: 1 }
+        );
+        assert_eq!(
+            parse(json!({"error": {"code": 2, "msg": "Invalid request"}, "id": 1})),
+            BinanceEvent::Error {
+                code: 2,
+                msg: "Invalid request".into()
+            }
+        );
+        assert_eq!(
+            parse(json!({"e": "trade", "a": [], "b": []})),
+            BinanceEvent::Unknown
+        );
+        assert_eq!(parse(json!({"a": [], "b": []})), BinanceEvent::Unknown);
+    }
+
+    #[test]
+    fn kraken_book_frames_are_parsed() {
+        let parse = |value: serde_json::Value| KrakenEvent::parse(&value.to_string()).unwrap();
+
+        assert_eq!(
+            parse(json!([
+                336,
+                {"a": [["101.0", "2.5", "1.1"]]},
+                {"b": [["100.0", "0.0", "1.2", "r"]], "c": "42"},
+                "book-10",
+                "XBT/USD"
+            ])),
+            KrakenEvent::Update {
+                pair: "XBT/USD".into(),
+                bids: vec![KrakenLevel(
+                    dec!(100.0),
+                    dec!(0.0),
+                    "1.2".into(),
+                    Some("r".into())
+                )],
+                asks: vec![KrakenLevel(dec!(101.0), dec!(2.5), "1.1".into(), None)],
+                checksum: Some(42),
+            }
+        );
+        assert!(matches!(
+            parse(json!([336, {"as": [], "bs": []}, "book-10", "XBT/USD"])),
+            KrakenEvent::Snapshot { .. }
+        ));
+        assert_eq!(parse(json!({"event": "heartbeat"})), KrakenEvent::Heartbeat);
+        assert_eq!(
+            parse(json!([
+                42,
+                [["1.0", "1.0", "1.0", "b", "l", ""]],
+                "trade",
+                "XBT/USD"
+            ])),
+            KrakenEvent::Unknown
+        );
+        assert_eq!(
+            parse(json!({"event": "pong", "reqid": 1})),
+            KrakenEvent::Pong
+        );
+        assert_eq!(parse(json!({"event": "trade"})), KrakenEvent::Unknown);
+    }
+
+    #[test]
+    fn coinbase_events_are_parsed_by_type() {
+        let parse = |value: serde_json::Value| CoinbaseEvent::parse(&value.to_string()).unwrap();
+
+        assert_eq!(
+            parse(json!({
+                "type": "l2update",
+                "product_id": "BTC-USD",
+                "time": "2022-05-04T12:00:00.000000Z",
+                "changes": [["buy", "100.1", "0.5"], ["sell", "101.2", "0"]],
+            })),
+            CoinbaseEvent::L2Update {
+                product_id: "BTC-USD".into(),
+                changes: vec![
+                    (CoinbaseSide::Buy, dec!(100.1), dec!(0.5)),
+                    (CoinbaseSide::Sell, dec!(101.2), dec!(0)),
+                ],
+            }
+        );
+        assert_eq!(
+            parse(json!({"type": "subscriptions", "channels": [{"name": "level2_batch"}]})),
+            CoinbaseEvent::Subscriptions
+        );
+        assert_eq!(
+            parse(json!({
+                "type": "heartbeat",
+                "sequence": 90,
+                "last_trade_id": 20,
+                "product_id": "BTC-USD",
+                "time": "2014-11-07T08:19:28.464459Z",
+            })),
+            CoinbaseEvent::Heartbeat {
+                product_id: "BTC-USD".into(),
+                sequence: 90,
+            }
+        );
+        assert_eq!(
+            parse(json!({"type": "match", "product_id": "BTC-USD"})),
+            CoinbaseEvent::Unknown
+        );
+    }
+
+    #[test]
+    fn bitfinex_frames_are_parsed_by_payload() {
+        let parse = |value: serde_json::Value| BitfinexEvent::parse(&value.to_string()).unwrap();
+
+        assert_eq!(
+            parse(json!([17082, [[7254.7, 3, 3.3], [7254.8, 1, -0.2]]])),
+            BitfinexEvent::Snapshot {
+                channel_id: 17082,
+                levels: vec![
+                    BitfinexLevel(dec!(7254.7), 3, dec!(3.3)),
+                    BitfinexLevel(dec!(7254.8), 1, dec!(-0.2)),
+                ],
+            }
+        );
+        assert_eq!(
+            parse(json!([17082, [7254.5, 0, 1]])),
+            BitfinexEvent::Update {
+                channel_id: 17082,
+                level: BitfinexLevel(dec!(7254.5), 0, dec!(1)),
+            }
+        );
+        assert_eq!(
+            parse(json!([17082, "hb"])),
+            BitfinexEvent::Heartbeat { channel_id: 17082 }
+        );
+        assert_eq!(
+            parse(json!({
+                "event": "subscribed", "channel": "book", "chanId": 17082,
+                "symbol": "tBTCUSD", "prec": "P0", "freq": "F0", "len": "25",
+            })),
+            BitfinexEvent::Subscribed {
+                channel_id: 17082,
+                channel: "book".into(),
+                symbol: Some("tBTCUSD".into()),
+            }
+        );
+        assert_eq!(parse(json!([17082, "cs", 42])), BitfinexEvent::Unknown);
+    }
+
+    #[test]
+    fn huobi_events_are_parsed_by_field() {
+        let parse = |value: serde_json::Value| HuobiEvent::parse(&value.to_string()).unwrap();
+
+        assert_eq!(
+            parse(json!({"ping": 1492420473027_u64})),
+            HuobiEvent::Ping(1492420473027)
+        );
+        assert_eq!(
+            parse(json!({
+                "ch": "market.btcusdt.depth.step0",
+                "ts": 1489474082831_u64,
+                "tick": {"bids": [[9999.38, 0.5]], "asks": [[10000.2, 1.25]], "version": 100},
+            })),
+            HuobiEvent::Depth(HuobiBook {
+                bids: vec![(dec!(9999.38), dec!(0.5))],
+                asks: vec![(dec!(10000.2), dec!(1.25))],
+            })
+        );
+        assert_eq!(
+            parse(
+                json!({"status": "error", "err-code": "bad-request", "err-msg": "invalid topic"})
+            ),
+            HuobiEvent::Response {
+                status: "error".into(),
+                message: Some("invalid topic".into())
+            }
+        );
+        assert_eq!(
+            parse(json!({"ch": "market.btcusdt.trade.detail", "tick": {}})),
+            HuobiEvent::Unknown
+        );
+    }
+
+    #[test]
+    fn bitstamp_events_are_parsed_by_event_name() {
+        let parse = |value: serde_json::Value| BitstampEvent::parse(&value.to_string());
+
+        assert_eq!(
+            parse(json!({
+                "event": "data",
+                "channel": "order_book_btcusd",
+                "data": {"timestamp": "1", "bids": [["100", "1"]], "asks": [["101", "2"]]},
+            }))
+            .unwrap(),
+            BitstampEvent::Data {
+                channel: "order_book_btcusd".into(),
+                data: BitstampBook {
+                    bids: vec![(dec!(100), dec!(1))],
+                    asks: vec![(dec!(101), dec!(2))],
+                },
+            }
+        );
+        assert_eq!(
+            parse(json!({"event": "bts:subscription_succeeded", "channel": "order_book_btcusd", "data": {}}))
+                .unwrap(),
+            BitstampEvent::Subscribed {
+                channel: "order_book_btcusd".into()
+            }
+        );
+        assert_eq!(
+            parse(json!({"event": "bts:heartbeat", "channel": "", "data": {"status": "success"}}))
+                .unwrap(),
+            BitstampEvent::Heartbeat
+        );
+        assert_eq!(
+            parse(json!({"event": "trade", "channel": "live_trades_btcusd", "data": {}})).unwrap(),
+            BitstampEvent::Unknown
+        );
+        assert!(matches!(
+            parse(json!({"bids": [], "asks": []})),
+            Err(Error::ParseError(_))
+        ));
+    }
 }
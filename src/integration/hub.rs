@@ -0,0 +1,616 @@
+//! Ingestion hub type.
+//!
+//! This module implements the ingestion hub. The hub is started with the server
+//! and owns the single set of exchange connections. It maintains a book per
+//! configured instrument and fans their summaries out to any number of
+//! subscribers, so that the number of exchange connections does not grow with
+//! the number of clients.
+//!
+//! The exchange channels of an instrument are only subscribed while the
+//! instrument has subscribers. The channels of several exchanges mapped to the
+//! same instrument by the instrument registry share a consolidated book.
+//!
+//! The summaries of a book are made once per view of the book requested by the
+//! subscribers, such as a grouping of the levels into price buckets. They carry
+//! the state of the connection to each exchange of the book, and a summary is
+//! published when the state changes.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+
+use super::adapter::AdapterRegistry;
+use super::api_service::{ApiService, ChannelCommand};
+use super::summary::new_summary;
+use super::transport::StopSender;
+use crate::configuration::ExchangeConfig;
+use crate::prelude::{
+    BookBatch, BookKind, BookQueue, BookUpdate, ConnectionState, Error, Exchange,
+    ExchangeConnection, FillSize, InstrumentRegistry, MarketImpact, Result, Side, Summary,
+    TickBook,
+};
+
+/// Number of summaries kept for the subscribers lagging behind, the largest
+/// lag of a client.
+pub(crate) const SUMMARY_CAPACITY: usize = 64;
+
+/// Largest depth of the summaries of a view.
+const MAX_DEPTH: usize = 500;
+
+/// The [`BookHub`] type is the ingestion hub of the exchange books.
+///
+/// The exchange channels of an instrument are subscribed when the first client
+/// subscribes to the instrument, and unsubscribed once the last one is gone for
+/// the linger period. The instruments whose mid price is a currency rate stay
+/// subscribed. Dropping the hub unsubscribes from the exchanges and closes the
+/// connections.
+pub struct BookHub {
+    instruments: Arc<HashMap<String, InstrumentFeed>>,
+    size: usize,
+    stop_request: StopSender,
+    /// Demand of the instruments whose mid price is a rate, kept subscribed.
+    _rates: Vec<DemandGuard>,
+}
+
+/// The [`InstrumentFeed`] type is the state of the book of an instrument
+/// shared with its subscribers.
+struct InstrumentFeed {
+    state: Mutex<FeedState>,
+    demand: Arc<Demand>,
+}
+
+/// The [`FeedState`] type is the book of an instrument and the summaries of
+/// its views.
+struct FeedState {
+    symbol: String,
+    bid_book: BookQueue,
+    ask_book: BookQueue,
+    /// Levels of the exchanges at their published prices, when the instrument
+    /// has reference data.
+    ticks: Option<TickBook>,
+    /// Latest state of the connection to each exchange of the book.
+    connections: BTreeMap<Exchange, ConnectionState>,
+    views: HashMap<BookView, ViewFeed>,
+    /// Whether a summary of the book was published.
+    published: bool,
+    /// Number of book updates applied, to tell whether the book changed
+    /// since a point in time.
+    generation: u64,
+}
+
+/// The [`ViewFeed`] type is the summaries of a view of a book.
+struct ViewFeed {
+    summaries: broadcast::Sender<Summary>,
+    latest: Option<Summary>,
+}
+
+impl ViewFeed {
+    fn new(latest: Option<Summary>) -> Self {
+        Self {
+            summaries: broadcast::channel(SUMMARY_CAPACITY).0,
+            latest,
+        }
+    }
+}
+
+/// The [`BookView`] type is the depth of the summaries of a book, and the
+/// increment its consolidated levels are grouped by, if any.
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct BookView {
+    /// Number of levels, 0 for the depth of the hub.
+    pub depth: usize,
+    /// Price increment of the buckets of the consolidated levels.
+    pub grouping: Option<Decimal>,
+}
+
+impl BookHub {
+    /// Starts new [`BookHub`] connected to the channels of the configured
+    /// exchanges.
+    ///
+    /// Each channel is streamed on its own connection, opened on the first
+    /// subscription to the instrument of the channel, and the summaries are
+    /// made of the top `size` levels of the books. The levels are rounded to
+    /// the increments of their instrument in the instrument registry.
+    pub fn start(
+        size: usize,
+        registry: AdapterRegistry,
+        instruments: InstrumentRegistry,
+        config: Vec<ExchangeConfig>,
+        linger: Duration,
+    ) -> Self {
+        let config: Vec<_> = config
+            .iter()
+            .flat_map(ExchangeConfig::per_channel)
+            .map(|mut channel| {
+                channel.instrument =
+                    Some(instruments.symbol_of(&channel.exchange, &channel.channel));
+                channel
+            })
+            .collect();
+
+        let mut feeds = HashMap::new();
+        let mut wanted = HashMap::new();
+        for channel in &config {
+            feeds.entry(channel.symbol()).or_insert_with_key(|symbol| {
+                let (wanted_tx, wanted_rx) = watch::channel(false);
+                wanted.insert(symbol.clone(), wanted_rx);
+                InstrumentFeed {
+                    state: Mutex::new(FeedState::new(symbol, &instruments)),
+                    demand: Arc::new(Demand::new(wanted_tx, linger)),
+                }
+            });
+        }
+        let feeds = Arc::new(feeds);
+        let rates = instruments
+            .rates()
+            .instruments()
+            .filter_map(|symbol| match feeds.get(symbol) {
+                Some(feed) => Some(Demand::acquire(&feed.demand)),
+                None => {
+                    tracing::error!("no exchange channel for the rate of '{}'", symbol);
+                    None
+                }
+            })
+            .collect();
+
+        let (book_tx, book_rx) = mpsc::channel(size);
+        let (stop_tx, stop_rx) = oneshot::channel();
+        tokio::spawn(ingest(size, registry, config, book_tx, wanted, stop_rx));
+        tokio::spawn(maintain_books(book_rx, Arc::clone(&feeds), instruments));
+
+        Self {
+            instruments: feeds,
+            size,
+            stop_request: StopSender::new(stop_tx),
+            _rates: rates,
+        }
+    }
+
+    /// Subscribes to the summaries of the book of an instrument.
+    ///
+    /// An empty symbol selects the instrument when a single one is configured.
+    /// The exchange channels of the instrument stay subscribed as long as the
+    /// returned [`Subscription`] is alive.
+    pub fn subscribe(&self, symbol: &str) -> Result<Subscription> {
+        self.subscribe_view(symbol, BookView::default())
+    }
+
+    /// Subscribes to the summaries of a view of the book of an instrument.
+    ///
+    /// The depth of the view is capped, and the summaries of a view are made
+    /// as long as it has subscribers.
+    pub fn subscribe_view(&self, symbol: &str, mut view: BookView) -> Result<Subscription> {
+        let instrument = self.instrument(symbol)?;
+        view.depth = match view.depth {
+            0 => self.size,
+            depth => depth.min(MAX_DEPTH),
+        };
+        view.grouping = view.grouping.map(|grouping| grouping.normalize());
+
+        let mut state = instrument.lock();
+        let summary = state.published.then(|| state.summary(&view));
+        let feed = state
+            .views
+            .entry(view)
+            .or_insert_with(|| ViewFeed::new(summary));
+        Ok(Subscription {
+            latest: feed.latest.clone(),
+            summaries: feed.summaries.subscribe(),
+            _demand: Demand::acquire(&instrument.demand),
+        })
+    }
+
+    /// Returns the fill of an order against the book of an instrument.
+    ///
+    /// The exchange channels of the instrument are subscribed for the fill if
+    /// needed, and the fill waits for the book to be updated after the
+    /// subscription, so that a lingering or stale book is not quoted. When the
+    /// side of the book is empty, the fill waits for the following updates of
+    /// the book, up to the wait.
+    pub async fn impact(
+        &self,
+        symbol: &str,
+        side: Side,
+        size: FillSize,
+        wait: Duration,
+    ) -> Result<MarketImpact> {
+        let mut subscription = self.subscribe(symbol)?;
+        let generation = self.instrument(symbol)?.lock().generation;
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            let impact = self.fill(symbol, generation, side, size)?;
+            if !impact.fills.is_empty() {
+                return Ok(impact);
+            }
+
+            match tokio::time::timeout_at(deadline, subscription.summaries.recv()).await {
+                Ok(Ok(_) | Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) | Err(_) => return Ok(impact),
+            }
+        }
+    }
+
+    /// Returns the fill of an order against the book of an instrument updated
+    /// since a generation, and against an empty book otherwise.
+    fn fill(
+        &self,
+        symbol: &str,
+        generation: u64,
+        side: Side,
+        size: FillSize,
+    ) -> Result<MarketImpact> {
+        let state = self.instrument(symbol)?.lock();
+        if state.generation == generation {
+            let bids = BookQueue::new(BookKind::Bids);
+            let asks = BookQueue::new(BookKind::Asks);
+            return Ok(MarketImpact::new(&bids, &asks, side, size));
+        }
+
+        Ok(MarketImpact::new(
+            &state.bid_book,
+            &state.ask_book,
+            side,
+            size,
+        ))
+    }
+
+    /// Returns the feed of an instrument.
+    ///
+    /// An empty symbol selects the instrument when a single one is configured.
+    fn instrument(&self, symbol: &str) -> Result<&InstrumentFeed> {
+        match symbol {
+            "" if self.instruments.len() == 1 => self.instruments.values().next(),
+            symbol => self.instruments.get(symbol),
+        }
+        .ok_or_else(|| Error::UnknownInstrument(symbol.into()))
+    }
+}
+
+impl InstrumentFeed {
+    fn lock(&self) -> std::sync::MutexGuard<'_, FeedState> {
+        self.state.lock().expect("feed lock poisoned")
+    }
+}
+
+impl FeedState {
+    /// Creates the empty book of an instrument.
+    fn new(symbol: &str, registry: &InstrumentRegistry) -> Self {
+        let mut bid_book = BookQueue::new(BookKind::Bids);
+        let mut ask_book = BookQueue::new(BookKind::Asks);
+        if let Some(instrument) = registry.get(symbol) {
+            bid_book.set_precision(instrument.price_precision);
+            ask_book.set_precision(instrument.price_precision);
+        }
+        for (exchange, fees) in registry.fees_of(symbol) {
+            bid_book.set_fees(exchange.clone(), fees);
+            ask_book.set_fees(exchange, fees);
+        }
+        Self {
+            symbol: symbol.into(),
+            bid_book,
+            ask_book,
+            ticks: registry.get(symbol).cloned().map(TickBook::new),
+            connections: BTreeMap::new(),
+            views: HashMap::new(),
+            published: false,
+            generation: 0,
+        }
+    }
+
+    /// Publishes the summary of each view of the book.
+    ///
+    /// The views left without subscriber are dropped.
+    fn publish(&mut self) {
+        let mut views = std::mem::take(&mut self.views);
+        views.retain(|_, feed| feed.summaries.receiver_count() > 0);
+        for (view, feed) in &mut views {
+            let summary = self.summary(view);
+            // Sending fails when there is no subscriber, which is not an error.
+            let _ = feed.summaries.send(summary.clone());
+            feed.latest = Some(summary);
+        }
+        self.views = views;
+        self.published = true;
+    }
+
+    /// Creates the summary of a view of the book.
+    fn summary(&self, view: &BookView) -> Summary {
+        Summary {
+            connections: self
+                .connections
+                .iter()
+                .map(|(exchange, state)| ExchangeConnection::new(exchange, state))
+                .collect(),
+            ..new_summary(
+                &self.symbol,
+                &self.bid_book,
+                &self.ask_book,
+                view.depth,
+                view.grouping,
+            )
+        }
+    }
+
+    /// Aggregates a book update at the ticks of the instrument, if it has
+    /// reference data.
+    ///
+    /// Returns `None` if the level of the update is invalid.
+    fn normalize(&mut self, update: BookUpdate) -> Option<BookUpdate> {
+        match &mut self.ticks {
+            Some(ticks) => ticks.apply(update),
+            None => Some(update),
+        }
+    }
+
+    /// Applies a book update to the books.
+    ///
+    /// Returns `true` if the update changes the books or the state of a
+    /// connection.
+    fn apply(&mut self, update: BookUpdate) -> bool {
+        match update {
+            BookUpdate::Level(kind, level) => {
+                tracing::debug!(
+                    "received book '{}' level {:?} from exchange: {}'",
+                    kind.as_ref(),
+                    level,
+                    level.exchange.as_ref(),
+                );
+
+                match kind {
+                    BookKind::Asks => self.ask_book.push(level),
+                    BookKind::Bids => self.bid_book.push(level),
+                };
+            }
+            BookUpdate::Delete(kind, level) => {
+                match kind {
+                    BookKind::Asks => self.ask_book.remove(&level.exchange, &level.price),
+                    BookKind::Bids => self.bid_book.remove(&level.exchange, &level.price),
+                };
+            }
+            BookUpdate::Clear(exchange) => {
+                tracing::info!("clearing books from exchange: {}", exchange.as_ref());
+                self.ask_book.clear(&exchange);
+                self.bid_book.clear(&exchange);
+            }
+            BookUpdate::Connection(exchange, state) => {
+                tracing::info!("exchange '{}' is {:?}", exchange.as_ref(), state);
+                return self.connections.insert(exchange, state.clone()) != Some(state);
+            }
+        }
+
+        self.generation += 1;
+        true
+    }
+}
+
+impl Drop for BookHub {
+    fn drop(&mut self) {
+        let _ = self.stop_request.try_stop();
+    }
+}
+
+/// The [`Subscription`] type is a subscription to the summaries of the book of
+/// an instrument.
+pub struct Subscription {
+    /// Latest summary of the book, if any.
+    pub latest: Option<Summary>,
+
+    /// Receiver of the following summaries.
+    pub summaries: broadcast::Receiver<Summary>,
+
+    _demand: DemandGuard,
+}
+
+/// The [`Demand`] type counts the subscribers of the hub, and signals whether
+/// the exchange channels are wanted.
+struct Demand {
+    state: Mutex<DemandState>,
+    wanted: watch::Sender<bool>,
+    linger: Duration,
+}
+
+#[derive(Default)]
+struct DemandState {
+    subscribers: usize,
+    /// Number of releases, to tell whether a lingering release is the last.
+    releases: u64,
+}
+
+impl Demand {
+    fn new(wanted: watch::Sender<bool>, linger: Duration) -> Self {
+        Self {
+            state: Mutex::new(DemandState::default()),
+            wanted,
+            linger,
+        }
+    }
+
+    /// Adds a subscriber, subscribing the exchange channels for the first one.
+    fn acquire(demand: &Arc<Self>) -> DemandGuard {
+        let mut state = demand.lock();
+        state.subscribers += 1;
+        if !*demand.wanted.borrow() {
+            let _ = demand.wanted.send(true);
+        }
+
+        DemandGuard(Arc::clone(demand))
+    }
+
+    /// Removes a subscriber, unsubscribing the exchange channels once the last
+    /// one is gone for the linger period.
+    fn release(demand: &Arc<Self>) {
+        let mut state = demand.lock();
+        state.subscribers -= 1;
+        state.releases += 1;
+        if state.subscribers > 0 {
+            return;
+        }
+
+        let release = state.releases;
+        let demand = Arc::clone(demand);
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    tokio::time::sleep(demand.linger).await;
+                    demand.unsubscribe_after(release);
+                });
+            }
+            Err(_) => {
+                drop(state);
+                demand.unsubscribe_after(release);
+            }
+        }
+    }
+
+    /// Unsubscribes the exchange channels if no subscriber came or left since
+    /// the release.
+    fn unsubscribe_after(&self, release: u64) {
+        let state = self.lock();
+        if state.subscribers == 0 && state.releases == release {
+            let _ = self.wanted.send(false);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, DemandState> {
+        self.state.lock().expect("demand lock poisoned")
+    }
+}
+
+/// The [`DemandGuard`] type releases a subscriber of the hub when dropped.
+struct DemandGuard(Arc<Demand>);
+
+impl Drop for DemandGuard {
+    fn drop(&mut self) {
+        Demand::release(&self.0);
+    }
+}
+
+/// Streams the books of the exchange channels until a stop is requested.
+///
+/// The connections are opened by their exchange service, which retries the
+/// failed ones with a backoff. The exchange channels of an instrument are
+/// subscribed and unsubscribed as the demand for its book changes.
+#[tracing::instrument(
+    name = "Ingest exchange books",
+    skip(registry, config, book_sender, wanted, stop)
+)]
+async fn ingest(
+    size: usize,
+    registry: AdapterRegistry,
+    config: Vec<ExchangeConfig>,
+    book_sender: mpsc::Sender<BookBatch>,
+    wanted: HashMap<String, watch::Receiver<bool>>,
+    stop: oneshot::Receiver<bool>,
+) {
+    let mut api = ApiService::with_registry(size, registry);
+    for val in &config {
+        if let Err(e) = api.add(val) {
+            tracing::error!(
+                "failed to add connection to exchange '{}': {}",
+                val.exchange,
+                e
+            );
+        }
+    }
+    if api.services.is_empty() {
+        tracing::error!("no connection was added");
+    }
+
+    let mut channels: HashMap<_, Vec<_>> = HashMap::new();
+    for service in &api.services {
+        channels
+            .entry(service.config.symbol())
+            .or_default()
+            .push((service.config.channel.clone(), service.commands()));
+    }
+    let demands = wanted.into_iter().map(|(symbol, wanted)| {
+        let channels = channels.remove(&symbol).unwrap_or_default();
+        follow_demand(wanted, channels)
+    });
+    tokio::join!(
+        api.watch(book_sender, stop),
+        futures_util::future::join_all(demands)
+    );
+}
+
+/// Subscribes or unsubscribes the exchange channels of an instrument when the
+/// demand changes.
+async fn follow_demand(
+    mut wanted: watch::Receiver<bool>,
+    channels: Vec<(String, mpsc::Sender<ChannelCommand>)>,
+) {
+    let mut subscribed = false;
+    while wanted.changed().await.is_ok() {
+        let is_wanted = *wanted.borrow();
+        if is_wanted == subscribed {
+            continue;
+        }
+        subscribed = is_wanted;
+
+        for (channel, commands) in &channels {
+            let command = if is_wanted {
+                ChannelCommand::Subscribe(channel.clone())
+            } else {
+                ChannelCommand::Unsubscribe
+            };
+            if let Err(e) = commands.send(command).await {
+                tracing::error!("failed to change subscription of '{}': {}", channel, e);
+            }
+        }
+    }
+}
+
+/// Applies the batches of book updates in arrival order, and publishes the
+/// summaries of the views of the book of the instrument after each batch
+/// changing it.
+///
+/// A batch is applied at once, so that the summaries never show a partly
+/// applied exchange message. The levels are aggregated at the ticks of their
+/// instrument, and the invalid ones are dropped.
+///
+/// The prices of the exchanges quoting an instrument in another currency are
+/// converted at the latest rates when the summary of the book is made, so a
+/// new live rate shows in the next summary of the books it converts.
+#[tracing::instrument(name = "Maintain books", skip(batches, instruments, registry))]
+async fn maintain_books(
+    mut batches: mpsc::Receiver<BookBatch>,
+    instruments: Arc<HashMap<String, InstrumentFeed>>,
+    mut registry: InstrumentRegistry,
+) {
+    while let Some(batch) = batches.recv().await {
+        let instrument = match instruments.get(&batch.symbol) {
+            Some(instrument) => instrument,
+            None => {
+                tracing::debug!("ignoring book of instrument '{}'", batch.symbol);
+                continue;
+            }
+        };
+        let mut state = instrument.lock();
+        let mut changed = false;
+        for update in batch.updates {
+            if let Some(update) = state.normalize(update) {
+                changed |= state.apply(update);
+            }
+        }
+        if !changed {
+            continue;
+        }
+
+        let FeedState {
+            bid_book, ask_book, ..
+        } = &mut *state;
+        if registry.update_rate(&batch.symbol, bid_book, ask_book) {
+            tracing::debug!("rate of instrument '{}' changed", batch.symbol);
+        }
+        for (exchange, rate) in registry.rates_of(&batch.symbol) {
+            bid_book.set_rate(exchange.clone(), rate);
+            ask_book.set_rate(exchange, rate);
+        }
+
+        state.publish();
+    }
+}
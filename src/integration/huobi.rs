@@ -0,0 +1,116 @@
+//! Huobi integration.
+//!
+//! This module implements the Huobi (HTX) market depth adapter. Huobi sends
+//! gzip compressed binary frames, and pings the client at the application
+//! level: a ping not answered with a pong of the same value closes the
+//! connection. Each depth message is a snapshot of the top levels, so the
+//! Huobi book is cleared before the levels are applied.
+
+use async_trait::async_trait;
+use tungstenite::Message;
+
+use super::adapter::{BookDecoder, ExchangeAdapter};
+use super::event::{self, HuobiEvent};
+use super::transport::Compression;
+use crate::configuration::ExchangeConfig;
+use crate::prelude::{BookUpdate, Exchange, Result};
+
+/// The Huobi exchange.
+pub const HUOBI: Exchange = Exchange::from_static("huobi");
+
+/// The [`HuobiAdapter`] type is the adapter of the Huobi depth channel.
+pub struct HuobiAdapter;
+
+impl ExchangeAdapter for HuobiAdapter {
+    fn exchange(&self) -> Exchange {
+        HUOBI
+    }
+
+    fn subscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+        Ok(request("sub", &config.channel))
+    }
+
+    fn unsubscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+        Ok(request("unsub", &config.channel))
+    }
+
+    fn compression(&self) -> Compression {
+        Compression::Gzip
+    }
+
+    fn decoder(&self, _config: &ExchangeConfig) -> Result<Box<dyn BookDecoder>> {
+        Ok(Box::new(HuobiDecoder::default()))
+    }
+}
+
+/// Creates a request of the depth channel of the symbol.
+fn request(kind: &str, symbol: &str) -> Message {
+    Message::Text(
+        serde_json::json!({
+            kind: format!("market.{}.depth.step0", symbol.to_lowercase()),
+            "id": "orderbook"
+        })
+        .to_string(),
+    )
+}
+
+/// The [`HuobiDecoder`] type decodes the messages of a Huobi connection.
+#[derive(Debug, Default)]
+struct HuobiDecoder {
+    replies: Vec<Message>,
+}
+
+#[async_trait]
+impl BookDecoder for HuobiDecoder {
+    #[tracing::instrument(name = "Decode Huobi message", skip(self, message))]
+    async fn decode(&mut self, message: Message) -> Result<Vec<BookUpdate>> {
+        let text = match message {
+            Message::Text(text) => text,
+            _ => return Ok(vec![]),
+        };
+
+        match HuobiEvent::parse(&text)? {
+            HuobiEvent::Depth(book) => {
+                let updates = std::iter::once(BookUpdate::Clear(HUOBI))
+                    .chain(BookUpdate::levels(&HUOBI, book.bids, book.asks))
+                    .collect();
+                return Ok(updates);
+            }
+            HuobiEvent::Ping(ping) => {
+                let pong = serde_json::json!({ "pong": ping }).to_string();
+                self.replies.push(Message::Text(pong));
+            }
+            HuobiEvent::Response { status, message } => match message {
+                Some(message) => tracing::error!("Huobi request {}: {}", status, message),
+                None => tracing::info!("Huobi request {}", status),
+            },
+            HuobiEvent::Unknown => event::record_unknown(&HUOBI, &text),
+        }
+
+        Ok(vec![])
+    }
+
+    fn replies(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.replies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn decoder_replies_to_ping() {
+        let mut decoder = HuobiDecoder::default();
+        let ping = Message::Text(json!({"ping": 42}).to_string());
+
+        assert_eq!(decoder.decode(ping).await.unwrap(), vec![]);
+        assert_eq!(
+            decoder.replies(),
+            vec![Message::Text(json!({"pong": 42}).to_string())]
+        );
+        assert_eq!(decoder.replies(), vec![]);
+    }
+}
@@ -0,0 +1,290 @@
+//! Kraken integration.
+//!
+//! This module implements the Kraken `book` channel adapter. The channel sends
+//! a snapshot of the top levels after the subscription, then the levels that
+//! changed. Kraken does not delete the levels pushed out of the subscribed
+//! depth, so the decoder keeps a copy of the book to truncate it.
+//!
+//! Each update carries the CRC32 checksum of the top ten levels of the book
+//! once the update is applied. A mismatch means the local book diverged, and
+//! is resolved by resubscribing to receive a new snapshot.
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use tungstenite::Message;
+
+use super::adapter::{BookDecoder, ExchangeAdapter};
+use super::event::{self, KrakenEvent, KrakenLevel};
+use crate::configuration::ExchangeConfig;
+use crate::prelude::{BookKind, BookUpdate, Error, Exchange, Level, Result};
+
+/// The Kraken exchange.
+pub const KRAKEN: Exchange = Exchange::from_static("kraken");
+
+/// Number of levels of the subscribed book.
+const BOOK_DEPTH: usize = 10;
+
+/// Number of levels of each side of the book covered by the checksum.
+const CHECKSUM_DEPTH: usize = 10;
+
+/// The [`KrakenAdapter`] type is the adapter of the Kraken book channel.
+pub struct KrakenAdapter;
+
+impl ExchangeAdapter for KrakenAdapter {
+    fn exchange(&self) -> Exchange {
+        KRAKEN
+    }
+
+    fn subscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+        Ok(request("subscribe", &config.channel))
+    }
+
+    fn unsubscribe_message(&self, config: &ExchangeConfig) -> Result<Message> {
+        Ok(request("unsubscribe", &config.channel))
+    }
+
+    fn heartbeat_message(&self) -> Option<Message> {
+        Some(Message::Text(
+            serde_json::json!({"event": "ping"}).to_string(),
+        ))
+    }
+
+    fn decoder(&self, _config: &ExchangeConfig) -> Result<Box<dyn BookDecoder>> {
+        Ok(Box::new(KrakenDecoder::default()))
+    }
+}
+
+/// Creates a request of the book channel of the pair.
+fn request(event: &str, pair: &str) -> Message {
+    Message::Text(
+        serde_json::json!({
+            "event": event,
+            "pair": [pair],
+            "subscription": {"name": "book", "depth": BOOK_DEPTH}
+        })
+        .to_string(),
+    )
+}
+
+/// The [`KrakenDecoder`] type maintains the book of a Kraken connection.
+#[derive(Debug, Default)]
+struct KrakenDecoder {
+    bids: BTreeMap<Decimal, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+    synced: bool,
+}
+
+#[async_trait]
+impl BookDecoder for KrakenDecoder {
+    #[tracing::instrument(name = "Decode Kraken message", skip(self, message))]
+    async fn decode(&mut self, message: Message) -> Result<Vec<BookUpdate>> {
+        let text = match message {
+            Message::Text(text) => text,
+            _ => return Ok(vec![]),
+        };
+
+        match KrakenEvent::parse(&text)? {
+            KrakenEvent::Snapshot { bids, asks, .. } => return Ok(self.snapshot(bids, asks)),
+            KrakenEvent::Update {
+                bids,
+                asks,
+                checksum,
+                ..
+            } => return self.update(bids, asks, checksum),
+            KrakenEvent::Heartbeat => tracing::debug!("received Kraken heartbeat"),
+            KrakenEvent::Pong => tracing::debug!("received Kraken pong"),
+            KrakenEvent::SubscriptionStatus {
+                status,
+                pair,
+                error_message,
+            } => match error_message {
+                Some(message) => tracing::error!("Kraken subscription error: {}", message),
+                None => tracing::info!("Kraken subscription to {:?} {}", pair, status),
+            },
+            KrakenEvent::SystemStatus { status } => {
+                tracing::info!("Kraken system status: {}", status)
+            }
+            KrakenEvent::Unknown => event::record_unknown(&KRAKEN, &text),
+        }
+
+        Ok(vec![])
+    }
+}
+
+impl KrakenDecoder {
+    /// Replaces the book with a snapshot.
+    fn snapshot(&mut self, bids: Vec<KrakenLevel>, asks: Vec<KrakenLevel>) -> Vec<BookUpdate> {
+        self.bids.clear();
+        self.asks.clear();
+        self.synced = true;
+
+        let mut updates = vec![BookUpdate::Clear(KRAKEN)];
+        updates.extend(self.apply(BookKind::Asks, asks));
+        updates.extend(self.apply(BookKind::Bids, bids));
+        updates
+    }
+
+    /// Applies the changed levels and verifies the checksum of the book.
+    ///
+    /// Updates received before a snapshot are ignored.
+    fn update(
+        &mut self,
+        bids: Vec<KrakenLevel>,
+        asks: Vec<KrakenLevel>,
+        checksum: Option<u32>,
+    ) -> Result<Vec<BookUpdate>> {
+        if !self.synced {
+            return Ok(vec![]);
+        }
+
+        let mut updates = self.apply(BookKind::Asks, asks);
+        updates.extend(self.apply(BookKind::Bids, bids));
+
+        if let Some(expected) = checksum {
+            let computed = self.checksum();
+            if computed != expected {
+                self.synced = false;
+                return Err(Error::ChecksumMismatch { expected, computed });
+            }
+        }
+
+        Ok(updates)
+    }
+
+    /// Applies levels to a side of the book, then truncates it to the
+    /// subscribed depth.
+    fn apply(&mut self, kind: BookKind, levels: Vec<KrakenLevel>) -> Vec<BookUpdate> {
+        let book = match kind {
+            BookKind::Bids => &mut self.bids,
+            BookKind::Asks => &mut self.asks,
+        };
+
+        let mut updates = vec![];
+        for level in levels {
+            let (price, amount) = level.price_amount();
+            if amount.is_zero() {
+                book.remove(&price);
+            } else {
+                book.insert(price, amount);
+            }
+            updates.push(BookUpdate::level(
+                kind.clone(),
+                Level::new(KRAKEN, price, amount),
+            ));
+        }
+
+        while book.len() > BOOK_DEPTH {
+            let worst = match kind {
+                BookKind::Bids => book.keys().next().copied(),
+                BookKind::Asks => book.keys().next_back().copied(),
+            };
+            if let Some((price, amount)) = worst.and_then(|p| book.remove_entry(&p)) {
+                updates.push(BookUpdate::Delete(
+                    kind.clone(),
+                    Level::new(KRAKEN, price, amount),
+                ));
+            }
+        }
+
+        updates
+    }
+
+    /// Computes the checksum of the top levels of the book.
+    ///
+    /// The checksum is the CRC32 of the concatenated prices and volumes of the
+    /// best asks then the best bids, without decimal point and leading zeros.
+    fn checksum(&self) -> u32 {
+        let asks = self.asks.iter().take(CHECKSUM_DEPTH);
+        let bids = self.bids.iter().rev().take(CHECKSUM_DEPTH);
+
+        let mut hasher = crc32fast::Hasher::new();
+        for (price, amount) in asks.chain(bids) {
+            for value in [price, amount] {
+                let digits = value.to_string().replace('.', "");
+                hasher.update(digits.trim_start_matches('0').as_bytes());
+            }
+        }
+        hasher.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Session ending on the book of the checksum example of the Kraken
+    /// documentation, with the checksum published by Kraken.
+    const FIXTURE: &str = include_str!("../../tests/fixtures/kraken/book.jsonl");
+
+    async fn decode_fixture(decoder: &mut KrakenDecoder) -> Vec<BookUpdate> {
+        let mut updates = vec![];
+        for line in FIXTURE.lines() {
+            let message = Message::Text(line.to_string());
+            updates.extend(decoder.decode(message).await.expect("failed to decode"));
+        }
+        updates
+    }
+
+    #[tokio::test]
+    async fn decoder_verifies_checksum_published_by_kraken() {
+        let mut decoder = KrakenDecoder::default();
+        let updates = decode_fixture(&mut decoder).await;
+
+        assert_eq!(updates[0], BookUpdate::Clear(KRAKEN));
+        assert_eq!(decoder.checksum(), 974947235);
+        assert_eq!(decoder.bids.len(), BOOK_DEPTH);
+        assert_eq!(decoder.asks.len(), BOOK_DEPTH);
+        assert_eq!(
+            decoder.bids.iter().next_back(),
+            Some((&"0.05000".parse().unwrap(), &"0.00000500".parse().unwrap()))
+        );
+        assert_eq!(
+            decoder.asks.iter().next(),
+            Some((&"0.05005".parse().unwrap(), &"0.00000500".parse().unwrap()))
+        );
+        assert!(updates.contains(&BookUpdate::Delete(
+            BookKind::Asks,
+            Level::new(
+                KRAKEN,
+                "0.05055".parse().unwrap(),
+                "0.00000500".parse().unwrap()
+            )
+        )));
+    }
+
+    #[tokio::test]
+    async fn decoder_rejects_checksum_mismatch() {
+        let mut decoder = KrakenDecoder::default();
+        decode_fixture(&mut decoder).await;
+
+        let update = serde_json::json!([
+            640,
+            {"b": [["0.05000", "0.00001000", "1582905489.439814"]], "c": "974947235"},
+            "book-10",
+            "ETH/XBT"
+        ]);
+        assert!(matches!(
+            decoder.decode(Message::Text(update.to_string())).await,
+            Err(Error::ChecksumMismatch {
+                expected: 974947235,
+                ..
+            })
+        ));
+
+        let update = serde_json::json!([
+            640,
+            {"b": [["0.05000", "0.00000500", "1582905490.439814"]], "c": "974947235"},
+            "book-10",
+            "ETH/XBT"
+        ]);
+        assert_eq!(
+            decoder
+                .decode(Message::Text(update.to_string()))
+                .await
+                .unwrap(),
+            vec![]
+        );
+    }
+}
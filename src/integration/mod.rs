@@ -1,5 +1,12 @@
+pub mod adapter;
 pub mod api_service;
+pub mod binance;
+pub mod bitfinex;
+pub mod bitstamp;
+pub mod coinbase;
 pub mod event;
-pub mod runtime;
+pub mod hub;
+pub mod huobi;
+pub mod kraken;
 pub mod summary;
 pub mod transport;
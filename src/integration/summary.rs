@@ -1,22 +1,52 @@
 //! Summary service type.
 //!
-//! This module implement the summary service.
+//! This module implement the summary service. The summaries are published by
+//! the ingestion hub and streamed to each client. The service also fills
+//! orders against the books of the hub, to tell their market impact.
 
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use async_trait::async_trait;
-use tokio::sync::{mpsc, oneshot};
+use rust_decimal::Decimal;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{broadcast, mpsc};
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::Stream;
 use tonic::{Request, Response, Status};
 
-use super::runtime::run_until_stopped;
-use super::transport::StopSender;
-use crate::prelude::{Book, BookKind, BookQueue, Configuration, Empty, OrderBook, Summary};
+use super::adapter::AdapterRegistry;
+use super::hub::{BookHub, BookView, Subscription, SUMMARY_CAPACITY};
+use crate::order_book::metric_to_string;
+use crate::prelude::{
+    Book, BookQueue, Configuration, ConsolidatedBook, ConsolidatedLevel, ExchangeSpread, FillSize,
+    Impact, ImpactRequest, InstrumentRegistry, Level, OrderBook, Side, SlowConsumerPolicy, Spread,
+    Summary, SummaryRequest,
+};
+
+/// Period the exchange channels stay subscribed after the last client
+/// disconnects, unless configured.
+const DEFAULT_LINGER: Duration = Duration::from_secs(30);
+
+/// Number of summaries a client may fall behind, unless configured or
+/// requested.
+const DEFAULT_MAX_LAG: usize = 16;
+
+/// Largest number of decimal places of the grouping of a view, so that the
+/// levels are not split into more buckets than a decimal holds.
+const MAX_GROUPING_SCALE: u32 = 8;
+
+/// Period a market impact request waits for the book of an instrument without
+/// subscriber.
+const IMPACT_WAIT: Duration = Duration::from_secs(5);
 
 pub struct SummaryService {
     pub config: Configuration,
+    hub: BookHub,
+    /// Number of summaries a client may fall behind, unless requested.
+    max_lag: usize,
 }
 
 impl Default for SummaryService {
@@ -28,51 +58,97 @@ impl Default for SummaryService {
 impl SummaryService {
     /// Creates new summary service.
     pub fn new() -> Self {
+        Self::with_registry(AdapterRegistry::default())
+    }
+
+    /// Creates new summary service streaming books with the adapters of a registry.
+    ///
+    /// The ingestion hub is started with the service, and shared by all the
+    /// summary streams.
+    pub fn with_registry(registry: AdapterRegistry) -> Self {
         let config = Configuration::new().expect("failed to get configuration");
-        Self { config }
+        let linger = config
+            .linger_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_LINGER);
+        let mut max_lag = config.max_lag.unwrap_or(DEFAULT_MAX_LAG);
+        if max_lag > SUMMARY_CAPACITY {
+            tracing::warn!(
+                "max lag {} exceeds the {} summaries kept by the hub",
+                max_lag,
+                SUMMARY_CAPACITY
+            );
+            max_lag = SUMMARY_CAPACITY;
+        }
+        let instruments =
+            InstrumentRegistry::load(&config).expect("failed to load instrument registry");
+        let hub = BookHub::start(
+            config.result_size,
+            registry,
+            instruments,
+            config.exchanges.clone(),
+            linger,
+        );
+        Self {
+            config,
+            hub,
+            max_lag,
+        }
     }
 }
 
 #[async_trait]
 impl OrderBook for SummaryService {
     type BookSummaryStream = SummaryStream;
-    #[tracing::instrument(name = "Book Summary", skip(self, _request))]
+    #[tracing::instrument(name = "Book Summary", skip(self, request))]
     async fn book_summary(
         &self,
-        _request: Request<Empty>,
+        request: Request<SummaryRequest>,
     ) -> Result<Response<Self::BookSummaryStream>, Status> {
-        let (book_tx, book_rx) = mpsc::channel(self.config.result_size);
-        let (stop_tx, stop_rx) = oneshot::channel();
-
-        let config = self.config.exchanges.clone();
-        let size = self.config.result_size;
-
-        tokio::spawn(async move {
-            run_until_stopped(size, config, book_tx, stop_rx).await;
-        });
-        let (tx, rx) = mpsc::channel(size);
+        let policy = StreamPolicy::new(request.get_ref(), self.max_lag)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let view =
+            book_view(request.get_ref()).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let subscription = self
+            .hub
+            .subscribe_view(&request.get_ref().symbol, view)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        let (tx, rx) = mpsc::channel(policy.capacity());
 
         tokio::spawn(async move {
-            stream_books(tx, book_rx, size).await;
+            stream_summaries(tx, subscription, policy).await;
         });
         let stream = SummaryStream {
             inner: ReceiverStream::new(rx),
-            stop_request: StopSender::new(stop_tx),
         };
 
         Ok(Response::new(stream))
     }
+
+    #[tracing::instrument(name = "Market Impact", skip(self, request))]
+    async fn market_impact(
+        &self,
+        request: Request<ImpactRequest>,
+    ) -> Result<Response<Impact>, Status> {
+        let request = request.into_inner();
+        let side = Side::from_i32(request.side)
+            .ok_or_else(|| Status::invalid_argument(format!("invalid side: {}", request.side)))?;
+        let size = fill_size(&request).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let impact = self
+            .hub
+            .impact(&request.symbol, side, size, IMPACT_WAIT)
+            .await
+            .map_err(|e| Status::not_found(e.to_string()))?;
+
+        Ok(Response::new(Impact {
+            symbol: request.symbol,
+            ..Impact::from(&impact)
+        }))
+    }
 }
 
 pub struct SummaryStream {
     inner: ReceiverStream<Result<Summary, Status>>,
-    stop_request: StopSender,
-}
-
-impl Drop for SummaryStream {
-    fn drop(&mut self) {
-        let _ = self.stop_request.try_stop();
-    }
 }
 
 impl Stream for SummaryStream {
@@ -83,40 +159,426 @@ impl Stream for SummaryStream {
     }
 }
 
-#[tracing::instrument(name = "Streams book", skip(summary, books, size))]
-async fn stream_books(
-    summary: mpsc::Sender<Result<Summary, Status>>,
-    mut books: mpsc::Receiver<(BookKind, Book)>,
-    size: usize,
+/// The [`StreamPolicy`] type is the handling of a client of a summary stream
+/// reading slower than the book updates.
+#[derive(Clone, Debug, PartialEq)]
+struct StreamPolicy {
+    kind: SlowConsumerPolicy,
+    /// Number of summaries the client may fall behind.
+    max_lag: usize,
+}
+
+impl StreamPolicy {
+    /// Creates new [`StreamPolicy`] of a request, with a default max lag.
+    ///
+    /// An unknown policy conflates the summaries. Returns an error if the
+    /// requested max lag exceeds the summaries kept by the hub.
+    fn new(request: &SummaryRequest, max_lag: usize) -> crate::prelude::Result<Self> {
+        let kind = SlowConsumerPolicy::from_i32(request.policy).unwrap_or_default();
+        let max_lag = match request.max_lag as usize {
+            0 => max_lag,
+            lag if lag > SUMMARY_CAPACITY => {
+                return Err(anyhow::anyhow!(
+                    "invalid max lag: {} exceeds {}",
+                    lag,
+                    SUMMARY_CAPACITY
+                )
+                .into())
+            }
+            lag => lag,
+        };
+
+        Ok(Self {
+            kind,
+            max_lag: max_lag.max(1),
+        })
+    }
+
+    /// Returns the capacity of the channel of the stream.
+    ///
+    /// A conflated stream only holds the latest summary, and a disconnected
+    /// stream keeps room for the error.
+    fn capacity(&self) -> usize {
+        match self.kind {
+            SlowConsumerPolicy::Conflate => 1,
+            SlowConsumerPolicy::Drop => self.max_lag,
+            SlowConsumerPolicy::Disconnect => self.max_lag + 1,
+        }
+    }
+}
+
+/// Streams the summaries of the hub to a client, starting with the latest one,
+/// until the client disconnects.
+///
+/// The subscription is dropped with the stream, and the exchange channels are
+/// unsubscribed once no client is left.
+#[tracing::instrument(name = "Stream summaries", skip(client, subscription))]
+async fn stream_summaries(
+    client: mpsc::Sender<Result<Summary, Status>>,
+    mut subscription: Subscription,
+    policy: StreamPolicy,
 ) {
-    let mut bid_book = BookQueue::with_capacity(BookKind::Bids, size);
-    let mut ask_book = BookQueue::with_capacity(BookKind::Asks, size);
-
-    while let Some((kind, book)) = books.recv().await {
-        tracing::info!(
-            "received book '{}' book {:?} from exchange: {}'",
-            kind.as_ref(),
-            book,
-            book.exchange,
-        );
-        let exchange = book.exchange.parse().unwrap();
+    let latest = subscription.latest.take();
+    forward_summaries(&client, latest, &mut subscription.summaries, &policy).await;
+}
+
+/// Forwards the summaries to a client with the slow consumer policy of the
+/// stream.
+///
+/// Each summary carries the number of summaries not sent to the client since
+/// the stream started.
+async fn forward_summaries(
+    client: &mpsc::Sender<Result<Summary, Status>>,
+    latest: Option<Summary>,
+    summaries: &mut broadcast::Receiver<Summary>,
+    policy: &StreamPolicy,
+) {
+    let mut dropped = 0;
+    let mut pending = latest;
 
-        match kind {
-            BookKind::Asks => ask_book.push(exchange, book),
-            BookKind::Bids => bid_book.push(exchange, book),
+    loop {
+        let mut summary = match pending.take() {
+            Some(summary) => summary,
+            None => match summaries.recv().await {
+                Ok(summary) => summary,
+                Err(RecvError::Lagged(_)) if policy.kind == SlowConsumerPolicy::Disconnect => {
+                    let _ = client.try_send(Err(lagging_client()));
+                    return;
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    dropped += skipped;
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            },
         };
 
-        let spread = ask_book.max_price() - bid_book.max_price();
+        if policy.kind == SlowConsumerPolicy::Conflate {
+            loop {
+                match summaries.try_recv() {
+                    Ok(latest) => {
+                        dropped += 1;
+                        summary = latest;
+                    }
+                    Err(TryRecvError::Lagged(skipped)) => dropped += skipped,
+                    Err(_) => break,
+                }
+            }
+        }
+        summary.dropped = dropped;
 
-        if let Err(e) = summary
-            .send(Ok(Summary {
-                spread: spread.abs().to_string(),
-                asks: ask_book.take(size),
-                bids: bid_book.take(size),
-            }))
+        let sent = match policy.kind {
+            SlowConsumerPolicy::Conflate => client.send(Ok(summary)).await.is_ok(),
+            SlowConsumerPolicy::Drop => match client.try_send(Ok(summary)) {
+                Err(TrySendError::Full(_)) => {
+                    dropped += 1;
+                    true
+                }
+                sent => sent.is_ok(),
+            },
+            SlowConsumerPolicy::Disconnect if client.capacity() > 1 => {
+                client.try_send(Ok(summary)).is_ok()
+            }
+            SlowConsumerPolicy::Disconnect => {
+                tracing::warn!("disconnecting client lagging behind by {}", policy.max_lag);
+                let _ = client.try_send(Err(lagging_client()));
+                false
+            }
+        };
+        if !sent {
+            tracing::info!("client disconnected");
+            return;
+        }
+    }
+}
+
+/// Returns the view of the book of a request.
+///
+/// Returns an error if the grouping is not a positive decimal of at most
+/// [`MAX_GROUPING_SCALE`] decimal places.
+fn book_view(request: &SummaryRequest) -> crate::prelude::Result<BookView> {
+    let grouping = match request.grouping.trim() {
+        "" => None,
+        grouping => match grouping.parse::<Decimal>() {
+            Ok(increment)
+                if increment > Decimal::ZERO
+                    && increment.normalize().scale() <= MAX_GROUPING_SCALE =>
+            {
+                Some(increment)
+            }
+            _ => return Err(anyhow::anyhow!("invalid grouping: {:?}", request.grouping).into()),
+        },
+    };
+
+    Ok(BookView {
+        depth: request.depth as usize,
+        grouping,
+    })
+}
+
+/// Returns the size of the order of a request.
+///
+/// Returns an error unless exactly one of the quantity and the notional is a
+/// positive decimal.
+fn fill_size(request: &ImpactRequest) -> crate::prelude::Result<FillSize> {
+    let parse = |value: &str| match value.parse::<Decimal>() {
+        Ok(value) if value > Decimal::ZERO => Ok(value),
+        _ => Err(anyhow::anyhow!("invalid size: {:?}", value)),
+    };
+
+    let size = match (request.quantity.trim(), request.notional.trim()) {
+        (quantity, "") if !quantity.is_empty() => FillSize::Quantity(parse(quantity)?),
+        ("", notional) if !notional.is_empty() => FillSize::Notional(parse(notional)?),
+        _ => {
+            return Err(anyhow::anyhow!("exactly one of quantity and notional must be set").into())
+        }
+    };
+
+    Ok(size)
+}
+
+/// Returns the error of a client disconnected for lagging behind.
+fn lagging_client() -> Status {
+    Status::resource_exhausted("client is lagging behind the book updates")
+}
+
+/// Creates the summary of the top n levels of the books of an instrument.
+///
+/// The consolidated levels are grouped into buckets of the grouping increment,
+/// if any.
+pub(crate) fn new_summary(
+    symbol: &str,
+    bid_book: &BookQueue,
+    ask_book: &BookQueue,
+    size: usize,
+    grouping: Option<Decimal>,
+) -> Summary {
+    let book = |level: &Level| Book {
+        symbol: symbol.into(),
+        ..Book::from(level)
+    };
+    let consolidated_book = |level: &ConsolidatedLevel| ConsolidatedBook {
+        books: level.levels.iter().map(book).collect(),
+        ..ConsolidatedBook::from(level)
+    };
+    let consolidate = |book_queue: &BookQueue| match grouping {
+        Some(increment) => book_queue.group(increment, size),
+        None => book_queue.consolidate(size),
+    };
+    let spread = Spread::new(bid_book, ask_book);
+    let mut exchanges = bid_book
+        .exchanges()
+        .chain(ask_book.exchanges())
+        .collect::<Vec<_>>();
+    exchanges.sort_by(|l, r| l.as_ref().cmp(r.as_ref()));
+    exchanges.dedup();
+
+    let exchange_spreads = exchanges
+        .into_iter()
+        .map(|exchange| {
+            let spread = Spread::of_exchange(bid_book, ask_book, exchange);
+            ExchangeSpread {
+                exchange: exchange.as_ref().into(),
+                best_bid: bid_book.best_of(exchange).as_ref().map(book),
+                best_ask: ask_book.best_of(exchange).as_ref().map(book),
+                spread: metric_to_string(spread.as_ref().map(Spread::spread)),
+                spread_bps: metric_to_string(spread.as_ref().and_then(Spread::spread_bps)),
+            }
+        })
+        .collect();
+
+    Summary {
+        spread: metric_to_string(spread.as_ref().map(Spread::spread)),
+        spread_bps: metric_to_string(spread.as_ref().and_then(Spread::spread_bps)),
+        mid_price: metric_to_string(spread.as_ref().map(Spread::mid_price)),
+        microprice: metric_to_string(spread.as_ref().and_then(Spread::microprice)),
+        asks: ask_book.take(size).iter().map(book).collect(),
+        bids: bid_book.take(size).iter().map(book).collect(),
+        consolidated_asks: consolidate(ask_book)
+            .iter()
+            .map(consolidated_book)
+            .collect(),
+        consolidated_bids: consolidate(bid_book)
+            .iter()
+            .map(consolidated_book)
+            .collect(),
+        exchange_spreads,
+        dropped: 0,
+        symbol: symbol.into(),
+        net_asks: ask_book.take_net(size).iter().map(book).collect(),
+        net_bids: bid_book.take_net(size).iter().map(book).collect(),
+        consolidated_net_asks: ask_book
+            .consolidate_net(size)
+            .iter()
+            .map(consolidated_book)
+            .collect(),
+        consolidated_net_bids: bid_book
+            .consolidate_net(size)
+            .iter()
+            .map(consolidated_book)
+            .collect(),
+        connections: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{sleep, timeout};
+
+    use super::*;
+
+    fn summary(spread: &str) -> Summary {
+        Summary {
+            spread: spread.into(),
+            ..Summary::default()
+        }
+    }
+
+    fn policy(kind: SlowConsumerPolicy, max_lag: u32) -> StreamPolicy {
+        let request = SummaryRequest {
+            policy: kind as i32,
+            max_lag,
+            ..SummaryRequest::default()
+        };
+        StreamPolicy::new(&request, DEFAULT_MAX_LAG).unwrap()
+    }
+
+    async fn recv(rx: &mut mpsc::Receiver<Result<Summary, Status>>) -> Result<Summary, Status> {
+        timeout(Duration::from_secs(1), rx.recv())
             .await
-        {
-            tracing::error!("failed so send summary: {}", e);
+            .expect("no summary received")
+            .expect("stream closed")
+    }
+
+    #[test]
+    fn book_view_parses_grouping() {
+        let request = |grouping: &str| SummaryRequest {
+            grouping: grouping.into(),
+            depth: 20,
+            ..SummaryRequest::default()
+        };
+
+        assert_eq!(
+            book_view(&request("10")).unwrap(),
+            BookView {
+                depth: 20,
+                grouping: Some(Decimal::TEN),
+            }
+        );
+        assert_eq!(book_view(&request("")).unwrap().grouping, None);
+        assert_eq!(
+            book_view(&request("0.00000001")).unwrap().grouping,
+            Some(Decimal::new(1, 8))
+        );
+        for grouping in [
+            "abc",
+            "0",
+            "-1",
+            "0.000000001",
+            "0.0000000000000000000000001",
+        ] {
+            assert!(book_view(&request(grouping)).is_err());
+        }
+    }
+
+    #[test]
+    fn fill_size_requires_a_single_positive_size() {
+        let request = |quantity: &str, notional: &str| ImpactRequest {
+            quantity: quantity.into(),
+            notional: notional.into(),
+            ..ImpactRequest::default()
+        };
+
+        assert_eq!(
+            fill_size(&request("1.5", "")).unwrap(),
+            FillSize::Quantity(Decimal::new(15, 1))
+        );
+        assert_eq!(
+            fill_size(&request("", "1000")).unwrap(),
+            FillSize::Notional(Decimal::ONE_THOUSAND)
+        );
+        for (quantity, notional) in [("", ""), ("1", "1"), ("0", ""), ("", "abc")] {
+            assert!(fill_size(&request(quantity, notional)).is_err());
         }
     }
+
+    #[test]
+    fn policy_defaults_max_lag() {
+        let policy = StreamPolicy::new(&SummaryRequest::default(), DEFAULT_MAX_LAG).unwrap();
+        assert_eq!(policy.kind, SlowConsumerPolicy::Conflate);
+        assert_eq!(policy.max_lag, DEFAULT_MAX_LAG);
+        assert_eq!(policy.capacity(), 1);
+    }
+
+    #[test]
+    fn policy_rejects_max_lag_beyond_hub_capacity() {
+        let request = |max_lag: usize| SummaryRequest {
+            policy: SlowConsumerPolicy::Drop as i32,
+            max_lag: max_lag as u32,
+            ..SummaryRequest::default()
+        };
+
+        let policy = StreamPolicy::new(&request(SUMMARY_CAPACITY), DEFAULT_MAX_LAG).unwrap();
+        assert_eq!(policy.capacity(), SUMMARY_CAPACITY);
+        assert!(StreamPolicy::new(&request(SUMMARY_CAPACITY + 1), DEFAULT_MAX_LAG).is_err());
+    }
+
+    #[tokio::test]
+    async fn conflated_stream_sends_latest_summary() {
+        let (tx, mut summaries) = broadcast::channel(8);
+        let policy = policy(SlowConsumerPolicy::Conflate, 0);
+        let (client, mut rx) = mpsc::channel(policy.capacity());
+        for spread in ["1", "2", "3"] {
+            tx.send(summary(spread)).unwrap();
+        }
+        drop(tx);
+        let forward = tokio::spawn(async move {
+            forward_summaries(&client, Some(summary("0")), &mut summaries, &policy).await;
+        });
+
+        let received = recv(&mut rx).await.unwrap();
+        assert_eq!((received.spread.as_str(), received.dropped), ("3", 3));
+        forward.await.unwrap();
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn dropping_stream_counts_dropped_summaries() {
+        let (tx, mut summaries) = broadcast::channel(8);
+        let (client, mut rx) = mpsc::channel(2);
+        let forward = tokio::spawn(async move {
+            let policy = policy(SlowConsumerPolicy::Drop, 2);
+            forward_summaries(&client, None, &mut summaries, &policy).await;
+        });
+
+        for spread in ["1", "2", "3"] {
+            tx.send(summary(spread)).unwrap();
+        }
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(recv(&mut rx).await.unwrap().spread, "1");
+        tx.send(summary("4")).unwrap();
+        drop(tx);
+        forward.await.unwrap();
+
+        let received = recv(&mut rx).await.unwrap();
+        assert_eq!((received.spread.as_str(), received.dropped), ("2", 0));
+        let received = recv(&mut rx).await.unwrap();
+        assert_eq!((received.spread.as_str(), received.dropped), ("4", 1));
+    }
+
+    #[tokio::test]
+    async fn lagging_client_is_disconnected() {
+        let (tx, mut summaries) = broadcast::channel(8);
+        let policy = policy(SlowConsumerPolicy::Disconnect, 1);
+        let (client, mut rx) = mpsc::channel(policy.capacity());
+        for spread in ["1", "2", "3"] {
+            tx.send(summary(spread)).unwrap();
+        }
+
+        forward_summaries(&client, None, &mut summaries, &policy).await;
+
+        assert_eq!(recv(&mut rx).await.unwrap().spread, "1");
+        let status = recv(&mut rx).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    }
 }
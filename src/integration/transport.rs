@@ -2,11 +2,15 @@
 //!
 //! This module defines various data structures and mechanism for transport.
 
+use std::io::Read;
 use std::pin::Pin;
+use std::time::Duration;
 
 use crate::error::Error;
 use async_trait::async_trait;
+use flate2::read::{DeflateDecoder, GzDecoder};
 use futures_util::{Sink, Stream};
+use rand::Rng;
 use tokio::sync::oneshot;
 use tungstenite::Message;
 
@@ -40,6 +44,86 @@ impl<T, I, E> SocketStream<I, E> for T where T: Stream<Item = Result<I, E>> + Si
 pub type WebSocketStream =
     Pin<Box<dyn SocketStream<Message, tungstenite::Error> + Send + Sync + 'static>>;
 
+/// The [`Backoff`] type computes the delays between connection attempts.
+///
+/// The delay doubles after each attempt up to a maximum, and is drawn between
+/// half and all of it so that clients do not reconnect in step.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    /// Creates new [`Backoff`] with the delay of the first attempt and the
+    /// maximum delay.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            attempt: 0,
+        }
+    }
+
+    /// Returns the number and the delay of the next attempt.
+    pub fn next_delay(&mut self) -> (u32, Duration) {
+        let exponent = self.attempt.min(16);
+        let delay = self.initial.saturating_mul(1 << exponent).min(self.max);
+        let delay = delay / 2 + delay.mul_f64(rand::thread_rng().gen_range(0.0..0.5));
+
+        self.attempt += 1;
+        (self.attempt, delay)
+    }
+
+    /// Resets the delay after a successful attempt.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// The [`Compression`] type is the compression of the binary frames of an
+/// exchange.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    /// Raw deflate stream, without zlib header.
+    Deflate,
+}
+
+impl Compression {
+    /// Converts a binary frame to a text frame, decompressing its payload.
+    ///
+    /// Other frames are returned unchanged.
+    pub fn decode_frame(&self, message: Message) -> Result<Message, Error> {
+        let payload = match message {
+            Message::Binary(payload) => payload,
+            message => return Ok(message),
+        };
+
+        let mut text = String::new();
+        match self {
+            Self::None => {
+                text = String::from_utf8(payload)
+                    .map_err(|e| anyhow::anyhow!("invalid binary frame: {}", e))?
+            }
+            Self::Gzip => {
+                GzDecoder::new(payload.as_slice())
+                    .read_to_string(&mut text)
+                    .map_err(|e| anyhow::anyhow!("invalid gzip frame: {}", e))?;
+            }
+            Self::Deflate => {
+                DeflateDecoder::new(payload.as_slice())
+                    .read_to_string(&mut text)
+                    .map_err(|e| anyhow::anyhow!("invalid deflate frame: {}", e))?;
+            }
+        }
+
+        Ok(Message::Text(text))
+    }
+}
+
 ///The transport traits encapsulate the operations required of a transport mechanism.
 #[async_trait]
 pub trait WebSocketTransport {
@@ -49,5 +133,73 @@ pub trait WebSocketTransport {
     async fn subscribe(&mut self, message: Message) -> Result<(), Error>;
 
     /// Unsubscribes from a channel.
-    async fn unsubscribe(&self) -> Result<(), Error>;
+    async fn unsubscribe(&mut self, message: Message) -> Result<(), Error>;
+
+    /// Closes the connection with a close handshake.
+    async fn close(&mut self) -> Result<(), Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::{DeflateEncoder, GzEncoder};
+
+    use super::*;
+
+    #[test]
+    fn backoff_delay_grows_up_to_maximum() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+
+        let delays = (0..6).map(|_| backoff.next_delay()).collect::<Vec<_>>();
+        for (index, (attempt, delay)) in delays.into_iter().enumerate() {
+            let max = Duration::from_millis(100 << index).min(Duration::from_secs(1));
+            assert_eq!(attempt, index as u32 + 1);
+            assert!(
+                delay >= max / 2 && delay <= max,
+                "{:?} not in {:?}",
+                delay,
+                max
+            );
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay().1 <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn binary_frames_are_decompressed_to_text() {
+        let text = r#"{"ping":1}"#;
+
+        let mut gzip = GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(text.as_bytes()).unwrap();
+        let frame = Message::Binary(gzip.finish().unwrap());
+        assert_eq!(
+            Compression::Gzip.decode_frame(frame).unwrap(),
+            Message::Text(text.into())
+        );
+
+        let mut deflate = DeflateEncoder::new(vec![], flate2::Compression::default());
+        deflate.write_all(text.as_bytes()).unwrap();
+        let frame = Message::Binary(deflate.finish().unwrap());
+        assert_eq!(
+            Compression::Deflate.decode_frame(frame).unwrap(),
+            Message::Text(text.into())
+        );
+
+        let frame = Message::Binary(text.as_bytes().to_vec());
+        assert_eq!(
+            Compression::None.decode_frame(frame).unwrap(),
+            Message::Text(text.into())
+        );
+        assert!(Compression::Gzip
+            .decode_frame(Message::Binary(text.as_bytes().to_vec()))
+            .is_err());
+        assert_eq!(
+            Compression::Gzip
+                .decode_frame(Message::Text(text.into()))
+                .unwrap(),
+            Message::Text(text.into())
+        );
+    }
 }
@@ -3,57 +3,57 @@
 //! This module defines the data structures for maintaining an order book.
 
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 
-use priority_queue::DoublePriorityQueue;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
 
-use crate::integration::event::{Event, EventData};
-use crate::prelude::{Error, Exchange};
+use crate::prelude::{ConsolidatedLevel, Exchange, Fees, Level};
 
 tonic::include_proto!("orderbook");
 
 /// The [`BookQueue`] type. the [See module level documentation](self).
+///
+/// A [`BookQueue`] is one side of a level 2 order book. It keeps a price to
+/// amount map per exchange, so levels from different exchanges never overwrite
+/// each other.
+///
+/// The levels are kept at the price published by their exchange, and are
+/// converted into the quote currency of the book when they are read, so that
+/// a change of rate applies to the whole book. The levels read carry their
+/// net prices once the fees of their exchange are paid.
 #[derive(Debug)]
 pub struct BookQueue {
     cap: usize,
     pub(super) kind: BookKind,
-    pub(super) books: DoublePriorityQueue<Exchange, Book>,
-}
-
-impl PartialOrd for Book {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-impl Eq for Book {}
-
-impl Ord for Book {
-    fn cmp(&self, other: &Self) -> Ordering {
-        let amount: Decimal = self.amount.parse().unwrap();
-        let price: Decimal = self.price.parse().unwrap();
-
-        let rh_amount: Decimal = other.amount.parse().unwrap();
-        let rh_price: Decimal = other.price.parse().unwrap();
-
-        match price.cmp(&rh_price) {
-            Ordering::Equal => amount.cmp(&rh_amount),
-            Ordering::Greater => Ordering::Less,
-            Ordering::Less => Ordering::Greater,
-        }
-    }
+    pub(super) levels: HashMap<Exchange, BTreeMap<Decimal, Decimal>>,
+    /// Rate of the exchanges quoting in another currency, `None` if unknown.
+    rates: HashMap<Exchange, Option<Decimal>>,
+    /// Number of decimal places of the converted prices.
+    precision: Option<u32>,
+    fees: HashMap<Exchange, Fees>,
 }
 
 /// The [`BookKind`] type is the different kind of books in an order book.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all(serialize = "snake_case"))]
 pub enum BookKind {
     Asks,
     Bids,
 }
 
+impl BookKind {
+    /// Compares two prices so that the best price of this side comes first.
+    ///
+    /// Bids are ordered by descending price and asks by ascending price.
+    pub fn cmp_price(&self, price: &Decimal, other: &Decimal) -> Ordering {
+        match self {
+            BookKind::Asks => price.cmp(other),
+            BookKind::Bids => other.cmp(price),
+        }
+    }
+}
+
 impl AsRef<str> for BookKind {
     fn as_ref(&self) -> &str {
         match self {
@@ -70,56 +70,12 @@ impl Book {
             price: price.into(),
             amount: amount.into(),
             exchange: exchange.into(),
+            symbol: String::new(),
+            raw_price: price.into(),
+            taker_price: price.into(),
+            maker_price: price.into(),
         }
     }
-
-    /// Publishes books to a channel.
-    #[tracing::instrument(name = "Publishes books to a channel", skip(book_sender, messages))]
-    pub async fn publish(
-        book_sender: mpsc::Sender<(BookKind, Book)>,
-        messages: Result<tungstenite::Message, tungstenite::Error>,
-    ) -> Result<(), Error> {
-        let messages = messages.unwrap();
-        let exchange: Exchange;
-        let EventData { bids, asks } = match serde_json::from_str::<Event>(&messages.into_text()?)
-            .map_err(Error::ParseError)
-        {
-            Ok(Event::Binance(event)) => {
-                exchange = Exchange::Binance;
-                event
-            }
-            Ok(Event::Bitstamp { data }) => {
-                exchange = Exchange::Bitstamp;
-                data
-            }
-            Err(e) => {
-                tracing::error!("failed to parse message: {}", e);
-                return Err(e);
-            }
-        };
-        let bid_sender = book_sender.clone();
-        let bid_exchange = exchange.clone();
-
-        tokio::spawn(async move {
-            for (price, amount) in bids {
-                let book = Book::new(&price, &amount, bid_exchange.as_ref());
-                if let Err(e) = bid_sender.send((BookKind::Bids, book)).await {
-                    tracing::error!("failed to publish book: {}", e);
-                }
-            }
-        });
-
-        tokio::spawn(async move {
-            for (price, amount) in asks {
-                let book = Book::new(&price, &amount, exchange.as_ref());
-                if let Err(e) = book_sender.send((BookKind::Asks, book)).await {
-                    tracing::error!("failed to publish book: {}", e);
-                }
-            }
-        });
-
-        Ok(())
-    }
 }
 
 impl BookQueue {
@@ -128,60 +84,125 @@ impl BookQueue {
         BookQueue {
             cap: 0,
             kind,
-            books: DoublePriorityQueue::new(),
+            levels: HashMap::new(),
+            rates: HashMap::new(),
+            precision: None,
+            fees: HashMap::new(),
         }
     }
 
     /// Creates new order book of the specified kind with the specified capacity.
+    ///
+    /// The capacity is the maximum number of price levels kept per exchange,
+    /// 0 means the depth is unbounded.
     pub fn with_capacity(kind: BookKind, capacity: usize) -> Self {
         BookQueue {
             cap: capacity,
             kind,
-            books: DoublePriorityQueue::with_capacity(capacity),
+            levels: HashMap::new(),
+            rates: HashMap::new(),
+            precision: None,
+            fees: HashMap::new(),
         }
     }
 
-    /// Adds a book to the order book.
+    /// Sets the rate converting the prices of an exchange into the quote
+    /// currency of the book.
     ///
-    /// # Example
+    /// The levels of an exchange with an unknown rate are left out of the
+    /// book until the rate is known.
+    pub fn set_rate(&mut self, exchange: Exchange, rate: Option<Decimal>) {
+        if rate == Some(Decimal::ONE) {
+            self.rates.remove(&exchange);
+        } else {
+            self.rates.insert(exchange, rate);
+        }
+    }
+
+    /// Sets the number of decimal places the converted prices are rounded to.
+    pub fn set_precision(&mut self, precision: u32) {
+        self.precision = Some(precision);
+    }
+
+    /// Sets the fees of an exchange, used for the net prices of its levels.
+    pub fn set_fees(&mut self, exchange: Exchange, fees: Fees) {
+        self.fees.insert(exchange, fees);
+    }
+
+    /// Adds a level to the order book.
     ///
+    /// The level replaces the level previously published by the same exchange
+    /// at the same price.
     ///
+    /// # Example
     ///
+    /// ```
     /// use orderbook::prelude::*;
+    /// use rust_decimal_macros::dec;
     ///
     /// let mut order_book = BookQueue::with_capacity(BookKind::Asks, 1);
-    /// let book = Book::new("2.1", "0.4", "bitstamp");
-    /// order_book.push(Exchange::Bitstamp, book);
+    /// order_book.push(Level::new(Exchange::BITSTAMP, dec!(2.1), dec!(0.4)));
     /// assert_eq!(order_book.len(), 1);
     /// ```
-    pub fn push(&mut self, exchange: Exchange, book: Book) {
-        self.books.push(exchange, book);
-        self.pop();
+    pub fn push(&mut self, level: Level) {
+        let levels = self.levels.entry(level.exchange).or_default();
+        levels.insert(level.price, level.amount);
+
+        while self.cap > 0 && levels.len() > self.cap {
+            let worst = match self.kind {
+                BookKind::Asks => levels.keys().next_back().copied(),
+                BookKind::Bids => levels.keys().next().copied(),
+            };
+            if let Some(price) = worst {
+                levels.remove(&price);
+            }
+        }
+    }
+
+    /// Removes the level of an exchange at the specified price.
+    pub fn remove(&mut self, exchange: &Exchange, price: &Decimal) -> Option<Level> {
+        let levels = self.levels.get_mut(exchange)?;
+        let amount = levels.remove(price);
+        if levels.is_empty() {
+            self.levels.remove(exchange);
+        }
+        amount.map(|amount| Level::new(exchange.clone(), *price, amount))
     }
 
-    /// Removes the minimum book from the order book.
+    /// Removes all the levels of an exchange.
+    pub fn clear(&mut self, exchange: &Exchange) {
+        self.levels.remove(exchange);
+    }
+
+    /// Removes the worst level from the order book.
     ///
-    /// # Example
+    /// The worst level is the lowest bid or the highest ask across all exchanges.
     ///
+    /// # Example
     ///
+    /// ```
     /// use orderbook::prelude::*;
+    /// use rust_decimal_macros::dec;
     ///
-    /// let mut order_book = BookQueue::with_capacity(BookKind::Bids, 1);
-    /// order_book.push(Exchange::Binance, Book::new("1.9", "3.7', "binance"));
-    /// order_book.push(Exchange::Bitstamp, Book::new("2.5", "4.1", "bitstamp"));
-    /// let value = Some((Exchange::Binance, Book::new("1.9", "3.7", "binance")));
+    /// let mut order_book = BookQueue::new(BookKind::Bids);
+    /// order_book.push(Level::new(Exchange::BINANCE, dec!(1.9), dec!(3.7)));
+    /// order_book.push(Level::new(Exchange::BITSTAMP, dec!(2.5), dec!(4.1)));
+    /// let value = Some(Level::new(Exchange::BINANCE, dec!(1.9), dec!(3.7)));
     /// assert_eq!(order_book.pop(), value);
     /// ```
-    pub fn pop(&mut self) -> Option<(Exchange, Book)> {
-        if self.len() == self.cap {
-            return self.books.pop_min();
-        }
-        None
+    pub fn pop(&mut self) -> Option<Level> {
+        let level = self
+            .levels
+            .iter()
+            .filter_map(|(exchange, levels)| self.converted_levels(exchange, levels).last())
+            .max_by(|l, r| self.kind.cmp_price(&l.price, &r.price))?;
+
+        self.remove(&level.exchange, &level.raw_price)
     }
 
-    /// Returns the number of books in the order book.
+    /// Returns the number of price levels in the order book.
     pub fn len(&self) -> usize {
-        self.books.len()
+        self.levels.values().map(BTreeMap::len).sum()
     }
 
     /// Returns `true` if the book order has a lenght of 0.
@@ -189,70 +210,459 @@ impl BookQueue {
         self.len() == 0
     }
 
-    /// Returns the top n books.
-    pub fn take(&self, n: usize) -> Vec<Book> {
-        self.books
-            .clone()
-            .into_sorted_iter()
-            .rev()
+    /// Returns the top n levels across all exchanges, best price first.
+    pub fn take(&self, n: usize) -> Vec<Level> {
+        self.take_by(n, |level| level.price)
+    }
+
+    /// Returns the top n levels across all exchanges, best taker price first.
+    pub fn take_net(&self, n: usize) -> Vec<Level> {
+        self.take_by(n, |level| level.taker_price)
+    }
+
+    /// Returns the top n prices across all exchanges, best price first.
+    ///
+    /// The levels of the exchanges at the same price are merged into one
+    /// consolidated level.
+    pub fn consolidate(&self, n: usize) -> Vec<ConsolidatedLevel> {
+        self.consolidate_by(n, |level| level.price)
+    }
+
+    /// Returns the top n taker prices across all exchanges, best price first.
+    ///
+    /// The levels of the exchanges at the same taker price are merged into one
+    /// consolidated level.
+    pub fn consolidate_net(&self, n: usize) -> Vec<ConsolidatedLevel> {
+        self.consolidate_by(n, |level| level.taker_price)
+    }
+
+    /// Returns the top n price buckets across all exchanges, best bucket first.
+    ///
+    /// The prices are grouped by multiples of the increment, bids rounded down
+    /// and asks rounded up, and the levels of an exchange in a bucket are
+    /// merged into one level at the bucket price. The levels whose bucket
+    /// overflows are skipped.
+    pub fn group(&self, increment: Decimal, n: usize) -> Vec<ConsolidatedLevel> {
+        let mut buckets: BTreeMap<Decimal, BTreeMap<Exchange, Decimal>> = BTreeMap::new();
+        for (exchange, levels) in &self.levels {
+            let mut count = 0;
+            let mut last = None;
+            for level in self.converted_levels(exchange, levels) {
+                let bucket = match self.bucket(level.price, increment) {
+                    Some(bucket) => bucket,
+                    None => continue,
+                };
+                if last != Some(bucket) {
+                    count += 1;
+                    if count > n {
+                        break;
+                    }
+                    last = Some(bucket);
+                }
+                *buckets
+                    .entry(bucket)
+                    .or_default()
+                    .entry(exchange.clone())
+                    .or_default() += level.amount;
+            }
+        }
+
+        let buckets: Box<dyn Iterator<Item = _>> = match self.kind {
+            BookKind::Asks => Box::new(buckets.into_iter()),
+            BookKind::Bids => Box::new(buckets.into_iter().rev()),
+        };
+        buckets
+            .take(n)
+            .map(|(price, amounts)| {
+                let levels = amounts
+                    .into_iter()
+                    .map(|(exchange, amount)| Level::new(exchange, price, amount))
+                    .collect();
+                ConsolidatedLevel::new(price, levels)
+            })
+            .collect()
+    }
+
+    /// Returns the bucket of a price, rounded down for bids and up for asks.
+    ///
+    /// Returns `None` if the number of buckets overflows.
+    fn bucket(&self, price: Decimal, increment: Decimal) -> Option<Decimal> {
+        let buckets = price.checked_div(increment)?;
+        let buckets = match self.kind {
+            BookKind::Asks => buckets.ceil(),
+            BookKind::Bids => buckets.floor(),
+        };
+        buckets.checked_mul(increment)
+    }
+
+    /// Returns the top n levels across all exchanges, ordered by a price of
+    /// the levels.
+    fn take_by(&self, n: usize, price: fn(&Level) -> Decimal) -> Vec<Level> {
+        let mut levels = self
+            .levels
+            .iter()
+            .flat_map(|(exchange, levels)| self.converted_levels(exchange, levels).take(n))
+            .collect::<Vec<_>>();
+
+        levels.sort_by(|l, r| {
+            self.kind
+                .cmp_price(&price(l), &price(r))
+                .then_with(|| l.exchange.as_ref().cmp(r.exchange.as_ref()))
+        });
+        levels.truncate(n);
+        levels
+    }
+
+    /// Returns the top n values of a price of the levels across all
+    /// exchanges, with the levels at each value.
+    fn consolidate_by(&self, n: usize, price: fn(&Level) -> Decimal) -> Vec<ConsolidatedLevel> {
+        let mut prices: BTreeMap<Decimal, Vec<Level>> = BTreeMap::new();
+        for (exchange, levels) in &self.levels {
+            for level in self.converted_levels(exchange, levels).take(n) {
+                prices.entry(price(&level)).or_default().push(level);
+            }
+        }
+
+        let prices: Box<dyn Iterator<Item = (Decimal, Vec<Level>)>> = match self.kind {
+            BookKind::Asks => Box::new(prices.into_iter()),
+            BookKind::Bids => Box::new(prices.into_iter().rev()),
+        };
+        prices
             .take(n)
-            .map(|(_, b)| b)
-            .collect::<Vec<_>>()
+            .map(|(price, levels)| ConsolidatedLevel::new(price, levels))
+            .collect()
+    }
+
+    /// Returns the best price across all exchanges, with the total amount at that price.
+    pub fn best(&self) -> Option<ConsolidatedLevel> {
+        self.consolidate(1).pop()
+    }
+
+    /// Returns the best level of an exchange.
+    pub fn best_of(&self, exchange: &Exchange) -> Option<Level> {
+        let levels = self.levels.get(exchange)?;
+        self.converted_levels(exchange, levels).next()
     }
 
-    /// Returns the max price.
+    /// Returns the exchanges with at least one level in the order book.
+    ///
+    /// The exchanges with an unknown rate are left out.
+    pub fn exchanges(&self) -> impl Iterator<Item = &Exchange> {
+        self.levels
+            .keys()
+            .filter(move |exchange| self.rate(exchange).is_some())
+    }
+
+    /// Returns the price at the top of the queue, the highest bid or the lowest
+    /// ask, or zero if the queue is empty.
     pub fn max_price(&self) -> Decimal {
-        self.books
-            .peek_max()
-            .map(|(_, b)| b.price.parse::<Decimal>().unwrap_or_default())
-            .unwrap_or_default()
+        self.best().map(|level| level.price).unwrap_or_default()
+    }
+
+    /// Returns the rate of an exchange, `None` if it is unknown.
+    fn rate(&self, exchange: &Exchange) -> Option<Decimal> {
+        self.rates
+            .get(exchange)
+            .copied()
+            .unwrap_or(Some(Decimal::ONE))
+    }
+
+    /// Returns the level of an exchange with its price converted at a rate,
+    /// and its net prices once the fees of the exchange are paid.
+    fn convert(
+        &self,
+        exchange: &Exchange,
+        rate: Decimal,
+        price: Decimal,
+        amount: Decimal,
+    ) -> Level {
+        let mut level = Level::new(exchange.clone(), price, amount);
+        if rate != Decimal::ONE {
+            level.price = self.round(price * rate);
+            level.taker_price = level.price;
+            level.maker_price = level.price;
+        }
+        if let Some(fees) = self.fees.get(exchange) {
+            level.taker_price = self.round(fees.taker_price(&self.kind, level.price));
+            level.maker_price = self.round(fees.maker_price(&self.kind, level.price));
+        }
+        level
+    }
+
+    /// Rounds a computed price to the precision of the book, if any.
+    fn round(&self, price: Decimal) -> Decimal {
+        match self.precision {
+            Some(precision) => price.round_dp(precision),
+            None => price,
+        }
+    }
+
+    /// Returns the levels of an exchange, best price first, with their prices
+    /// converted into the quote currency of the book.
+    ///
+    /// Returns no level if the rate of the exchange is unknown.
+    fn converted_levels<'a>(
+        &'a self,
+        exchange: &'a Exchange,
+        levels: &'a BTreeMap<Decimal, Decimal>,
+    ) -> impl Iterator<Item = Level> + 'a {
+        self.rate(exchange).into_iter().flat_map(move |rate| {
+            self.best_levels(levels)
+                .map(move |(price, amount)| self.convert(exchange, rate, *price, *amount))
+        })
+    }
+
+    /// Returns the levels of an exchange, best price first.
+    fn best_levels<'a>(
+        &self,
+        levels: &'a BTreeMap<Decimal, Decimal>,
+    ) -> Box<dyn DoubleEndedIterator<Item = (&'a Decimal, &'a Decimal)> + 'a> {
+        match self.kind {
+            BookKind::Asks => Box::new(levels.iter()),
+            BookKind::Bids => Box::new(levels.iter().rev()),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Book;
-    use crate::telemetry::tests::force_lazy;
-    use fake;
-    use tokio::sync::mpsc::channel;
-    use tungstenite::Message;
-
-    fn generate_message_data(ask: &str, bid: &str) -> String {
-        force_lazy();
-        let gen = || -> Vec<(String, String)> {
-            fake::vec![f64; 10]
-                .iter()
-                .map(ToString::to_string)
-                .zip(fake::vec![f64; 10].iter().map(ToString::to_string))
-                .collect()
-        };
+    use crate::prelude::{
+        BookKind, BookQueue, BookUpdate, ConsolidatedLevel, Exchange, Fees, Level,
+    };
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
 
-        serde_json::json!({
-            ask: gen(),
-            bid: gen(),
-        })
-        .to_string()
-    }
-
-    #[tokio::test]
-    async fn publish_binance_successfully() {
-        let data = generate_message_data("a", "b");
-        let (tx, _rx) = channel(10);
-        let result = Ok(Message::Text(data));
-        assert!(
-            Book::publish(tx, result).await.is_ok(),
-            "failed to publish books"
-        )
-    }
-
-    #[tokio::test]
-    async fn publish_bitstamp_successfully() {
-        let data = generate_message_data("asks", "bids");
-        let (tx, _rx) = channel(10);
-        let result = Ok(Message::Text(data));
-        assert!(
-            Book::publish(tx, result).await.is_ok(),
-            "failed to publish books"
-        )
+    #[test]
+    fn book_queue_keeps_levels_per_exchange() {
+        let mut bids = BookQueue::new(BookKind::Bids);
+        bids.push(Level::new(Exchange::BINANCE, dec!(10.0), dec!(1)));
+        bids.push(Level::new(Exchange::BINANCE, dec!(10.5), dec!(2)));
+        bids.push(Level::new(Exchange::BITSTAMP, dec!(10.2), dec!(3)));
+        bids.push(Level::new(Exchange::BINANCE, dec!(10.5), dec!(4)));
+
+        assert_eq!(bids.len(), 3);
+        assert_eq!(
+            bids.take(10),
+            vec![
+                Level::new(Exchange::BINANCE, dec!(10.5), dec!(4)),
+                Level::new(Exchange::BITSTAMP, dec!(10.2), dec!(3)),
+                Level::new(Exchange::BINANCE, dec!(10.0), dec!(1)),
+            ]
+        );
+        assert_eq!(bids.max_price(), dec!(10.5));
+    }
+
+    #[test]
+    fn book_queue_orders_asks_ascending_and_bounds_depth() {
+        let mut asks = BookQueue::with_capacity(BookKind::Asks, 2);
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(11.0), dec!(1)));
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(10.8), dec!(1)));
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(10.9), dec!(1)));
+        asks.push(Level::new(Exchange::BINANCE, dec!(10.8), dec!(2)));
+
+        assert_eq!(
+            asks.take(2),
+            vec![
+                Level::new(Exchange::BINANCE, dec!(10.8), dec!(2)),
+                Level::new(Exchange::BITSTAMP, dec!(10.8), dec!(1)),
+            ]
+        );
+        assert_eq!(asks.len(), 3);
+        assert_eq!(asks.max_price(), dec!(10.8));
+        assert_eq!(
+            asks.pop(),
+            Some(Level::new(Exchange::BITSTAMP, dec!(10.9), dec!(1)))
+        );
+    }
+
+    #[test]
+    fn book_queue_consolidates_levels_across_exchanges() {
+        let mut asks = BookQueue::new(BookKind::Asks);
+        asks.push(Level::new(Exchange::BINANCE, dec!(10.8), dec!(1)));
+        asks.push(Level::new(Exchange::BINANCE, dec!(10.9), dec!(2)));
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(10.8), dec!(3)));
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(11.0), dec!(4)));
+
+        let consolidated = asks.consolidate(2);
+        assert_eq!(
+            consolidated,
+            vec![
+                ConsolidatedLevel {
+                    price: dec!(10.8),
+                    amount: dec!(4),
+                    levels: vec![
+                        Level::new(Exchange::BITSTAMP, dec!(10.8), dec!(3)),
+                        Level::new(Exchange::BINANCE, dec!(10.8), dec!(1)),
+                    ],
+                },
+                ConsolidatedLevel {
+                    price: dec!(10.9),
+                    amount: dec!(2),
+                    levels: vec![Level::new(Exchange::BINANCE, dec!(10.9), dec!(2))],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn book_queue_converts_prices_of_exchanges() {
+        let mut asks = BookQueue::new(BookKind::Asks);
+        asks.set_precision(2);
+        asks.push(Level::new(Exchange::BINANCE, dec!(100.00), dec!(1)));
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(99.95), dec!(2)));
+
+        asks.set_rate(Exchange::BINANCE, None);
+        assert_eq!(
+            asks.take(10),
+            vec![Level::new(Exchange::BITSTAMP, dec!(99.95), dec!(2))]
+        );
+        assert_eq!(
+            asks.exchanges().collect::<Vec<_>>(),
+            vec![&Exchange::BITSTAMP]
+        );
+
+        asks.set_rate(Exchange::BINANCE, Some(dec!(0.99951)));
+        let best = asks.best().unwrap();
+        assert_eq!(best.price, dec!(99.95));
+        assert_eq!(best.amount, dec!(3));
+        assert_eq!(
+            best.levels[1],
+            Level {
+                raw_price: dec!(100.00),
+                ..Level::new(Exchange::BINANCE, dec!(99.95), dec!(1))
+            }
+        );
+
+        asks.remove(&Exchange::BINANCE, &dec!(100));
+        assert_eq!(asks.len(), 1);
+    }
+
+    #[test]
+    fn book_queue_ranks_net_levels_by_taker_price() {
+        let mut asks = BookQueue::new(BookKind::Asks);
+        asks.set_precision(2);
+        asks.set_fees(
+            Exchange::BINANCE,
+            Fees {
+                maker: dec!(0),
+                taker: dec!(0.002),
+            },
+        );
+        asks.push(Level::new(Exchange::BINANCE, dec!(100.00), dec!(1)));
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(100.10), dec!(2)));
+
+        assert_eq!(asks.take(1)[0].exchange, Exchange::BINANCE);
+        let net = asks.take_net(2);
+        assert_eq!(net[0].exchange, Exchange::BITSTAMP);
+        assert_eq!(net[1].taker_price, dec!(100.20));
+        assert_eq!(net[1].maker_price, dec!(100.00));
+        assert_eq!(net[1].price, dec!(100.00));
+
+        let consolidated = asks.consolidate_net(1);
+        assert_eq!(consolidated[0].price, dec!(100.10));
+        assert_eq!(consolidated[0].levels[0].exchange, Exchange::BITSTAMP);
+    }
+
+    #[test]
+    fn book_queue_groups_levels_into_buckets() {
+        let mut bids = BookQueue::new(BookKind::Bids);
+        bids.push(Level::new(Exchange::BINANCE, dec!(109.5), dec!(1)));
+        bids.push(Level::new(Exchange::BINANCE, dec!(101), dec!(2)));
+        bids.push(Level::new(Exchange::BITSTAMP, dec!(100), dec!(3)));
+        bids.push(Level::new(Exchange::BITSTAMP, dec!(99.9), dec!(4)));
+        bids.push(Level::new(Exchange::BITSTAMP, dec!(80), dec!(5)));
+
+        let grouped = bids.group(dec!(10), 2);
+        assert_eq!(
+            grouped,
+            vec![
+                ConsolidatedLevel::new(
+                    dec!(100),
+                    vec![
+                        Level::new(Exchange::BINANCE, dec!(100), dec!(3)),
+                        Level::new(Exchange::BITSTAMP, dec!(100), dec!(3)),
+                    ]
+                ),
+                ConsolidatedLevel::new(
+                    dec!(90),
+                    vec![Level::new(Exchange::BITSTAMP, dec!(90), dec!(4))]
+                ),
+            ]
+        );
+
+        let mut asks = BookQueue::new(BookKind::Asks);
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(100.5), dec!(1)));
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(101), dec!(2)));
+        assert_eq!(
+            asks.group(dec!(1), 10),
+            vec![ConsolidatedLevel::new(
+                dec!(101),
+                vec![Level::new(Exchange::BITSTAMP, dec!(101), dec!(3))]
+            )]
+        );
+    }
+
+    #[test]
+    fn book_queue_skips_levels_overflowing_tiny_buckets() {
+        let increment = dec!(0.0000000000000000000000001);
+        let mut bids = BookQueue::new(BookKind::Bids);
+        bids.push(Level::new(Exchange::BITSTAMP, dec!(100000), dec!(1)));
+        bids.push(Level::new(Exchange::BINANCE, dec!(1.5), dec!(2)));
+
+        assert_eq!(
+            bids.group(increment, 10),
+            vec![ConsolidatedLevel::new(
+                dec!(1.5),
+                vec![Level::new(Exchange::BINANCE, dec!(1.5), dec!(2))]
+            )]
+        );
+    }
+
+    fn apply(book_queue: &mut BookQueue, kind: BookKind, price: Decimal, amount: Decimal) {
+        let level = Level::new(Exchange::BITSTAMP, price, amount);
+        match BookUpdate::level(kind, level) {
+            BookUpdate::Level(_, level) => book_queue.push(level),
+            BookUpdate::Delete(_, level) => {
+                book_queue.remove(&level.exchange, &level.price);
+            }
+            BookUpdate::Clear(exchange) => book_queue.clear(&exchange),
+            BookUpdate::Connection(..) => (),
+        }
+    }
+
+    #[test]
+    fn book_queue_inserts_updates_and_deletes_bids() {
+        let level = |price, amount| Level::new(Exchange::BITSTAMP, price, amount);
+        let mut bids = BookQueue::new(BookKind::Bids);
+        apply(&mut bids, BookKind::Bids, dec!(10.0), dec!(1));
+        apply(&mut bids, BookKind::Bids, dec!(10.1), dec!(2));
+        assert_eq!(bids.take(1), vec![level(dec!(10.1), dec!(2))]);
+
+        apply(&mut bids, BookKind::Bids, dec!(10.1), dec!(5));
+        assert_eq!(bids.take(1), vec![level(dec!(10.1), dec!(5))]);
+
+        apply(&mut bids, BookKind::Bids, dec!(10.1), dec!(0.00000000));
+        assert_eq!(bids.take(10), vec![level(dec!(10.0), dec!(1))]);
+
+        apply(&mut bids, BookKind::Bids, dec!(10.0), dec!(0));
+        assert!(bids.is_empty());
+    }
+
+    #[test]
+    fn book_queue_inserts_updates_and_deletes_asks() {
+        let level = |price, amount| Level::new(Exchange::BITSTAMP, price, amount);
+        let mut asks = BookQueue::new(BookKind::Asks);
+        apply(&mut asks, BookKind::Asks, dec!(10.2), dec!(1));
+        apply(&mut asks, BookKind::Asks, dec!(10.3), dec!(2));
+        assert_eq!(asks.take(1), vec![level(dec!(10.2), dec!(1))]);
+
+        apply(&mut asks, BookKind::Asks, dec!(10.2), dec!(3));
+        assert_eq!(asks.take(1), vec![level(dec!(10.2), dec!(3))]);
+
+        apply(&mut asks, BookKind::Asks, dec!(10.2), dec!(0));
+        assert_eq!(asks.take(10), vec![level(dec!(10.3), dec!(2))]);
+
+        apply(&mut asks, BookKind::Asks, dec!(10.4), dec!(0));
+        assert_eq!(asks.len(), 1);
     }
 }
@@ -2,38 +2,50 @@
 //!
 //! This module implements the exchange data structure and operation.
 
+use std::borrow::Cow;
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
 use crate::prelude::Error;
 
-#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all(serialize = "snake_case"))]
-pub enum Exchange {
-    Binance,
-    Bitstamp,
+/// The [`Exchange`] type is the name of an exchange.
+///
+/// An exchange is identified by the lowercase name its adapter is registered
+/// with, so venues can be added without changing this type.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Exchange(Cow<'static, str>);
+
+impl Exchange {
+    pub const BINANCE: Self = Self::from_static("binance");
+    pub const BITSTAMP: Self = Self::from_static("bitstamp");
+
+    /// Creates new exchange from a static lowercase name.
+    pub const fn from_static(name: &'static str) -> Self {
+        Self(Cow::Borrowed(name))
+    }
+
+    /// Creates new exchange with the specified name.
+    pub fn new(name: &str) -> Self {
+        Self(Cow::Owned(name.to_lowercase()))
+    }
 }
 
 impl FromStr for Exchange {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let exchange = match s.to_lowercase().as_str() {
-            "binance" => Self::Binance,
-            "bitstamp" => Self::Bitstamp,
-            _ => return Err(anyhow::anyhow!(format!("unsupported exchange: {s}")).into()),
-        };
+        if s.trim().is_empty() {
+            return Err(anyhow::anyhow!("missing exchange name").into());
+        }
 
-        Ok(exchange)
+        Ok(Self::new(s.trim()))
     }
 }
 
 impl AsRef<str> for Exchange {
     fn as_ref(&self) -> &str {
-        match self {
-            Self::Binance => "binance",
-            Self::Bitstamp => "bitstamp",
-        }
+        &self.0
     }
 }
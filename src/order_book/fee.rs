@@ -0,0 +1,74 @@
+//! Fee type.
+//!
+//! This module defines the trading fees of an exchange, and the net prices of
+//! the levels once the fees are paid.
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use super::BookKind;
+
+/// The [`Fees`] type is the maker and taker fee rates of an instrument on an
+/// exchange, such as `0.001` for 10 basis points.
+///
+/// A negative maker fee is a rebate.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub struct Fees {
+    #[serde(default)]
+    pub maker: Decimal,
+    #[serde(default)]
+    pub taker: Decimal,
+}
+
+impl Fees {
+    /// Returns the net price of taking a level of a side of the book.
+    ///
+    /// Buying an ask costs the price plus the taker fee, and selling into a
+    /// bid earns the price less the taker fee.
+    pub fn taker_price(&self, kind: &BookKind, price: Decimal) -> Decimal {
+        net_price(kind, price, self.taker)
+    }
+
+    /// Returns the net price of joining a side of the book at a price.
+    ///
+    /// A resting bid costs the price plus the maker fee, and a resting ask
+    /// earns the price less the maker fee.
+    pub fn maker_price(&self, kind: &BookKind, price: Decimal) -> Decimal {
+        match kind {
+            BookKind::Asks => net_price(&BookKind::Bids, price, self.maker),
+            BookKind::Bids => net_price(&BookKind::Asks, price, self.maker),
+        }
+    }
+}
+
+/// Returns the price of trading against a side of the book with a fee rate.
+fn net_price(kind: &BookKind, price: Decimal, fee: Decimal) -> Decimal {
+    match kind {
+        BookKind::Asks => price * (Decimal::ONE + fee),
+        BookKind::Bids => price * (Decimal::ONE - fee),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn fees_adjust_prices_against_the_trader() {
+        let fees = Fees {
+            maker: dec!(-0.0001),
+            taker: dec!(0.001),
+        };
+
+        assert_eq!(fees.taker_price(&BookKind::Asks, dec!(100)), dec!(100.1));
+        assert_eq!(fees.taker_price(&BookKind::Bids, dec!(100)), dec!(99.9));
+        assert_eq!(fees.maker_price(&BookKind::Asks, dec!(100)), dec!(100.01));
+        assert_eq!(fees.maker_price(&BookKind::Bids, dec!(100)), dec!(99.99));
+        assert_eq!(
+            Fees::default().taker_price(&BookKind::Asks, dec!(100)),
+            dec!(100)
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Currency rate type.
+//!
+//! This module defines the rates converting the prices of the exchanges quoting
+//! an instrument in another currency, such as `usdt` for a `usd` instrument.
+//!
+//! A rate is either set in the configuration, read from a file at startup, or
+//! follows the mid price of the book of another instrument.
+
+use std::collections::HashMap;
+
+use config::{Config, File};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use super::{normalize_symbol, BookQueue, Spread};
+use crate::prelude::{Error, Result};
+
+/// The [`FxRate`] type is the source of the rate of a currency pair.
+///
+/// Exactly one of `rate`, `path` and `instrument` must be set.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct FxRate {
+    /// Currency converted from, such as `usdt`.
+    pub base: String,
+    /// Currency converted into, such as `usd`.
+    pub quote: String,
+    /// Static rate, the price of one `base` in `quote`.
+    pub rate: Option<Decimal>,
+    /// File holding the `rate`, read at startup.
+    pub path: Option<String>,
+    /// Instrument whose mid price is the rate, such as `usdtusd`.
+    pub instrument: Option<String>,
+}
+
+/// The [`FxRates`] type is the current rates of the configured currency pairs.
+#[derive(Clone, Debug, Default)]
+pub struct FxRates {
+    rates: HashMap<(String, String), Decimal>,
+    /// Currency pair following the mid price of each instrument.
+    live: HashMap<String, (String, String)>,
+}
+
+impl FxRates {
+    /// Creates new [`FxRates`] from the sources of the rates.
+    ///
+    /// The rates of the files are read at once. Returns an error if a source
+    /// does not set exactly one rate, or if a file cannot be read.
+    pub fn new(sources: Vec<FxRate>) -> Result<Self> {
+        let mut rates = Self::default();
+        for source in sources {
+            let pair = (source.base.to_lowercase(), source.quote.to_lowercase());
+            match (source.rate, &source.path, &source.instrument) {
+                (Some(rate), None, None) => {
+                    rates.rates.insert(pair, rate);
+                }
+                (None, Some(path), None) => {
+                    rates.rates.insert(pair, read_rate(path)?);
+                }
+                (None, None, Some(instrument)) => {
+                    rates.live.insert(normalize_symbol(instrument), pair);
+                }
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "rate of '{}/{}' must have exactly one of rate, path or instrument",
+                        source.base,
+                        source.quote
+                    )
+                    .into())
+                }
+            }
+        }
+
+        Ok(rates)
+    }
+
+    /// Returns the price of one `base` in `quote`, `None` if it is unknown.
+    pub fn rate(&self, base: &str, quote: &str) -> Option<Decimal> {
+        if base.eq_ignore_ascii_case(quote) {
+            return Some(Decimal::ONE);
+        }
+        self.rates
+            .get(&(base.to_lowercase(), quote.to_lowercase()))
+            .copied()
+    }
+
+    /// Returns the instruments whose mid price is a rate.
+    pub fn instruments(&self) -> impl Iterator<Item = &String> {
+        self.live.keys()
+    }
+
+    /// Sets the rate following the mid price of the book of an instrument.
+    ///
+    /// Returns `true` if the rate changed.
+    pub fn update(&mut self, symbol: &str, bid_book: &BookQueue, ask_book: &BookQueue) -> bool {
+        let pair = match self.live.get(symbol) {
+            Some(pair) => pair,
+            None => return false,
+        };
+        let rate = Spread::new(bid_book, ask_book).map(|spread| spread.mid_price());
+        match rate {
+            Some(rate) if rate > Decimal::ZERO => {
+                self.rates.insert(pair.clone(), rate) != Some(rate)
+            }
+            _ => self.rates.remove(pair).is_some(),
+        }
+    }
+}
+
+/// Reads the `rate` of a configuration file.
+fn read_rate(path: &str) -> Result<Decimal> {
+    Config::builder()
+        .add_source(File::with_name(path))
+        .build()
+        .and_then(|file| file.get("rate"))
+        .map_err(Error::ConfigError)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::prelude::{BookKind, Exchange, Level};
+
+    fn source(rate: Option<Decimal>, instrument: Option<&str>) -> FxRate {
+        FxRate {
+            base: "USDT".into(),
+            quote: "usd".into(),
+            rate,
+            path: None,
+            instrument: instrument.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn rates_have_a_single_source() {
+        let rates = FxRates::new(vec![source(Some(dec!(0.999)), None)]).unwrap();
+        assert_eq!(rates.rate("usdt", "USD"), Some(dec!(0.999)));
+        assert_eq!(rates.rate("usd", "usd"), Some(Decimal::ONE));
+        assert_eq!(rates.rate("usd", "usdt"), None);
+
+        assert!(FxRates::new(vec![source(None, None)]).is_err());
+        assert!(FxRates::new(vec![source(Some(dec!(1)), Some("usdtusd"))]).is_err());
+    }
+
+    #[test]
+    fn live_rate_follows_mid_price() {
+        let mut rates = FxRates::new(vec![source(None, Some("USDT/USD"))]).unwrap();
+        let mut bids = BookQueue::new(BookKind::Bids);
+        let mut asks = BookQueue::new(BookKind::Asks);
+        assert!(!rates.update("usdtusd", &bids, &asks));
+        assert_eq!(rates.rate("usdt", "usd"), None);
+
+        bids.push(Level::new(Exchange::BITSTAMP, dec!(0.9990), dec!(10)));
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(1.0000), dec!(10)));
+        assert!(!rates.update("btcusd", &bids, &asks));
+        assert!(rates.update("usdtusd", &bids, &asks));
+        assert!(!rates.update("usdtusd", &bids, &asks));
+        assert_eq!(rates.rate("usdt", "usd"), Some(dec!(0.9995)));
+
+        asks.clear(&Exchange::BITSTAMP);
+        assert!(rates.update("usdtusd", &bids, &asks));
+        assert_eq!(rates.rate("usdt", "usd"), None);
+    }
+}
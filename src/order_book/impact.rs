@@ -0,0 +1,269 @@
+//! Market impact type.
+//!
+//! This module implements the fill of an order walking the consolidated book,
+//! to tell the cost of trading a size at once across the exchanges.
+
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::{
+    metric_to_string, BookKind, BookQueue, Exchange, ExchangeFill, Impact, Side, Spread,
+    METRIC_SCALE,
+};
+
+/// The [`FillSize`] type is the size of an order to fill.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FillSize {
+    /// Quantity in base currency.
+    Quantity(Decimal),
+
+    /// Notional in quote currency.
+    Notional(Decimal),
+}
+
+/// The [`MarketImpact`] type is the fill of an order walking the book, best
+/// price first.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MarketImpact {
+    /// Side of the book walked by the order.
+    pub kind: BookKind,
+    pub quantity: Decimal,
+    pub notional: Decimal,
+    /// Whether the book was deep enough to fill the order.
+    pub complete: bool,
+    /// Price of the last level reached.
+    pub worst_price: Option<Decimal>,
+    pub mid_price: Option<Decimal>,
+    /// The fill of each exchange, largest quantity first.
+    pub fills: Vec<Fill>,
+}
+
+/// The [`Fill`] type is the part of an order filled on an exchange.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Fill {
+    pub exchange: Exchange,
+    pub quantity: Decimal,
+    pub notional: Decimal,
+}
+
+impl Fill {
+    /// Returns the average fill price.
+    pub fn average_price(&self) -> Option<Decimal> {
+        average(self.notional, self.quantity)
+    }
+}
+
+impl MarketImpact {
+    /// Fills an order of a size against a side of the books.
+    ///
+    /// A buy order walks the asks and a sell order walks the bids.
+    pub fn new(bids: &BookQueue, asks: &BookQueue, side: Side, size: FillSize) -> Self {
+        let book = match side {
+            Side::Buy => asks,
+            Side::Sell => bids,
+        };
+        let mut impact = Self {
+            kind: book.kind.clone(),
+            quantity: Decimal::ZERO,
+            notional: Decimal::ZERO,
+            complete: false,
+            worst_price: None,
+            mid_price: Spread::new(bids, asks).map(|spread| spread.mid_price()),
+            fills: vec![],
+        };
+
+        let mut fills: HashMap<Exchange, Fill> = HashMap::new();
+        for level in book.take(book.len()) {
+            let (quantity, notional) = match size {
+                FillSize::Quantity(quantity) => {
+                    let quantity = (quantity - impact.quantity).min(level.amount);
+                    (quantity, quantity * level.price)
+                }
+                FillSize::Notional(notional) => {
+                    let notional = (notional - impact.notional).min(level.amount * level.price);
+                    match notional.checked_div(level.price) {
+                        Some(quantity) => (quantity, notional),
+                        None => {
+                            tracing::warn!("skipping level {:?} without price", level);
+                            continue;
+                        }
+                    }
+                }
+            };
+            if quantity <= Decimal::ZERO {
+                break;
+            }
+
+            impact.quantity += quantity;
+            impact.notional += notional;
+            impact.worst_price = Some(level.price);
+
+            let fill = fills.entry(level.exchange.clone()).or_insert_with(|| Fill {
+                exchange: level.exchange,
+                quantity: Decimal::ZERO,
+                notional: Decimal::ZERO,
+            });
+            fill.quantity += quantity;
+            fill.notional += notional;
+        }
+        impact.complete = match size {
+            FillSize::Quantity(quantity) => impact.quantity >= quantity,
+            FillSize::Notional(notional) => impact.notional >= notional,
+        };
+
+        impact.fills = fills.into_values().collect();
+        impact.fills.sort_by(|l, r| {
+            r.quantity
+                .cmp(&l.quantity)
+                .then_with(|| l.exchange.as_ref().cmp(r.exchange.as_ref()))
+        });
+        impact
+    }
+
+    /// Returns the average fill price, `None` if nothing is filled.
+    pub fn average_price(&self) -> Option<Decimal> {
+        average(self.notional, self.quantity)
+    }
+
+    /// Returns the distance of the average fill price to the mid price,
+    /// positive when it costs the trader.
+    pub fn slippage(&self) -> Option<Decimal> {
+        let average_price = self.average_price()?;
+        let mid_price = self.mid_price?;
+        match self.kind {
+            BookKind::Asks => Some(average_price - mid_price),
+            BookKind::Bids => Some(mid_price - average_price),
+        }
+    }
+
+    /// Returns the slippage in basis points of the mid price.
+    pub fn slippage_bps(&self) -> Option<Decimal> {
+        let bps = self.slippage()?.checked_mul(Decimal::from(10_000))?;
+        Some(bps.checked_div(self.mid_price?)?.round_dp(METRIC_SCALE))
+    }
+}
+
+/// Returns the average price of a notional, `None` for a zero quantity.
+fn average(notional: Decimal, quantity: Decimal) -> Option<Decimal> {
+    Some(notional.checked_div(quantity)?.round_dp(METRIC_SCALE))
+}
+
+impl From<&Fill> for ExchangeFill {
+    fn from(fill: &Fill) -> Self {
+        ExchangeFill {
+            exchange: fill.exchange.as_ref().into(),
+            quantity: fill.quantity.to_string(),
+            notional: fill.notional.to_string(),
+            average_price: metric_to_string(fill.average_price()),
+        }
+    }
+}
+
+impl From<&MarketImpact> for Impact {
+    fn from(impact: &MarketImpact) -> Self {
+        let side = match impact.kind {
+            BookKind::Asks => Side::Buy,
+            BookKind::Bids => Side::Sell,
+        };
+
+        Impact {
+            symbol: String::new(),
+            side: side as i32,
+            quantity: impact.quantity.to_string(),
+            notional: impact.notional.to_string(),
+            complete: impact.complete,
+            average_price: metric_to_string(impact.average_price()),
+            worst_price: metric_to_string(impact.worst_price),
+            mid_price: metric_to_string(impact.mid_price),
+            slippage: metric_to_string(impact.slippage()),
+            slippage_bps: metric_to_string(impact.slippage_bps()),
+            fills: impact.fills.iter().map(ExchangeFill::from).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::prelude::Level;
+
+    fn books() -> (BookQueue, BookQueue) {
+        let mut bids = BookQueue::new(BookKind::Bids);
+        bids.push(Level::new(Exchange::BITSTAMP, dec!(99), dec!(1)));
+        let mut asks = BookQueue::new(BookKind::Asks);
+        asks.push(Level::new(Exchange::BINANCE, dec!(101), dec!(1)));
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(102), dec!(2)));
+        asks.push(Level::new(Exchange::BINANCE, dec!(104), dec!(5)));
+        (bids, asks)
+    }
+
+    #[test]
+    fn impact_walks_book_across_exchanges() {
+        let (bids, asks) = books();
+        let impact = MarketImpact::new(&bids, &asks, Side::Buy, FillSize::Quantity(dec!(4)));
+
+        assert!(impact.complete);
+        assert_eq!(impact.quantity, dec!(4));
+        assert_eq!(impact.notional, dec!(409));
+        assert_eq!(impact.average_price(), Some(dec!(102.25)));
+        assert_eq!(impact.worst_price, Some(dec!(104)));
+        assert_eq!(impact.mid_price, Some(dec!(100)));
+        assert_eq!(impact.slippage(), Some(dec!(2.25)));
+        assert_eq!(impact.slippage_bps(), Some(dec!(225)));
+        assert_eq!(
+            impact.fills,
+            vec![
+                Fill {
+                    exchange: Exchange::BINANCE,
+                    quantity: dec!(2),
+                    notional: dec!(205),
+                },
+                Fill {
+                    exchange: Exchange::BITSTAMP,
+                    quantity: dec!(2),
+                    notional: dec!(204),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn impact_fills_notional_and_reports_short_books() {
+        let (bids, asks) = books();
+        let impact = MarketImpact::new(&bids, &asks, Side::Buy, FillSize::Notional(dec!(305)));
+        assert!(impact.complete);
+        assert_eq!(impact.notional, dec!(305));
+        assert_eq!(impact.quantity, dec!(3));
+        assert_eq!(impact.worst_price, Some(dec!(102)));
+
+        let impact = MarketImpact::new(&bids, &asks, Side::Sell, FillSize::Quantity(dec!(3)));
+        assert!(!impact.complete);
+        assert_eq!(impact.quantity, dec!(1));
+        assert_eq!(impact.slippage(), Some(dec!(1)));
+
+        let impact = MarketImpact::new(
+            &bids,
+            &BookQueue::new(BookKind::Asks),
+            Side::Buy,
+            FillSize::Quantity(dec!(1)),
+        );
+        assert_eq!(impact.average_price(), None);
+        assert_eq!(impact.mid_price, None);
+        assert!(impact.fills.is_empty());
+    }
+
+    #[test]
+    fn impact_skips_levels_without_price() {
+        let (bids, mut asks) = books();
+        asks.push(Level::new(Exchange::BITSTAMP, dec!(0), dec!(1)));
+
+        let impact = MarketImpact::new(&bids, &asks, Side::Buy, FillSize::Notional(dec!(101)));
+        assert!(impact.complete);
+        assert_eq!(impact.quantity, dec!(1));
+        assert_eq!(impact.worst_price, Some(dec!(101)));
+    }
+}
@@ -0,0 +1,540 @@
+//! Instrument type.
+//!
+//! This module defines the reference data of the instruments, and the registry
+//! mapping the symbol of each exchange to a canonical instrument.
+//!
+//! The levels received from the exchanges are aggregated at the ticks of their
+//! instrument, the bids rounded down and the asks rounded up so the rounding
+//! never crosses the book, and invalid levels are rejected. The prices of the
+//! exchanges quoting an instrument in another currency are converted with the
+//! rates of the registry.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+use config::{Config, File};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserialize;
+
+use super::{BookKind, BookQueue, BookUpdate, Exchange, Fees, FxRates, Level};
+use crate::configuration::Configuration;
+use crate::prelude::{Error, Result};
+
+/// The [`Instrument`] type is the reference data of an instrument.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Instrument {
+    /// Canonical symbol, such as `btcusd`.
+    pub symbol: String,
+    pub base: String,
+    pub quote: String,
+    /// Smallest price increment.
+    pub tick_size: Decimal,
+    /// Smallest amount increment.
+    pub lot_size: Decimal,
+    /// Number of decimal places of the prices.
+    pub price_precision: u32,
+    /// Symbol of the instrument by exchange, when it differs from the
+    /// canonical symbol.
+    #[serde(default)]
+    pub venues: HashMap<String, String>,
+    /// Quote currency of the instrument by exchange, when it differs from the
+    /// instrument quote, such as `usdt` for a `usd` instrument.
+    #[serde(default)]
+    pub venue_quotes: HashMap<String, String>,
+    /// Maker and taker fees of the instrument by exchange.
+    #[serde(default)]
+    pub fees: HashMap<String, Fees>,
+}
+
+impl Instrument {
+    /// Rounds a price to a tick of a side of the book, down for the bids and
+    /// up for the asks, so a level is never shown at a better price.
+    ///
+    /// Returns `None` if the number of ticks overflows.
+    pub fn round_price(&self, kind: &BookKind, price: Decimal) -> Option<Decimal> {
+        let strategy = match kind {
+            BookKind::Bids => RoundingStrategy::ToNegativeInfinity,
+            BookKind::Asks => RoundingStrategy::ToPositiveInfinity,
+        };
+        let ticks = price
+            .checked_div(self.tick_size)?
+            .round_dp_with_strategy(0, strategy);
+        let mut price = ticks
+            .checked_mul(self.tick_size)?
+            .round_dp(self.price_precision);
+        price.rescale(self.price_precision);
+        Some(price)
+    }
+
+    /// Rounds an amount down to a whole number of lots.
+    ///
+    /// An amount smaller than a lot is kept, so its level is not deleted.
+    /// Returns `None` if the number of lots overflows.
+    pub fn round_amount(&self, amount: Decimal) -> Option<Decimal> {
+        let lots = amount
+            .checked_div(self.lot_size)?
+            .trunc()
+            .checked_mul(self.lot_size)?;
+        if lots.is_zero() && !amount.is_zero() {
+            tracing::debug!("keeping amount {} smaller than a lot", amount);
+            return Some(amount);
+        }
+        Some(lots)
+    }
+
+    /// Returns an error if the increments of the instrument are not positive.
+    fn validate(&self) -> Result<()> {
+        if self.tick_size <= Decimal::ZERO || self.lot_size <= Decimal::ZERO {
+            return Err(anyhow::anyhow!(
+                "instrument '{}' must have a positive tick size and lot size",
+                self.symbol
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Reads the `instruments` of a configuration file.
+fn read_instruments(path: &str) -> Result<Vec<Instrument>> {
+    Config::builder()
+        .add_source(File::with_name(path))
+        .build()
+        .and_then(|file| file.get("instruments"))
+        .map_err(Error::ConfigError)
+}
+
+/// Returns the symbol of a channel, such as `btcusd` for `BTC-USD` or
+/// `btc/usd`.
+pub fn normalize_symbol(channel: &str) -> String {
+    channel
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The [`InstrumentRegistry`] type is the reference data of the known
+/// instruments.
+#[derive(Clone, Debug, Default)]
+pub struct InstrumentRegistry {
+    instruments: HashMap<String, Instrument>,
+    /// Canonical symbol by exchange and exchange symbol.
+    venues: HashMap<(String, String), String>,
+    rates: FxRates,
+}
+
+impl InstrumentRegistry {
+    /// Creates new [`InstrumentRegistry`] of instruments.
+    ///
+    /// Returns an error if an instrument is invalid, or if an exchange symbol
+    /// is mapped to several instruments.
+    pub fn new(instruments: Vec<Instrument>) -> Result<Self> {
+        let mut registry = Self::default();
+        for mut instrument in instruments {
+            instrument.validate()?;
+            instrument.symbol = normalize_symbol(&instrument.symbol);
+
+            for (exchange, venue_symbol) in &instrument.venues {
+                let key = (exchange.to_lowercase(), normalize_symbol(venue_symbol));
+                if let Some(other) = registry.venues.insert(key, instrument.symbol.clone()) {
+                    return Err(anyhow::anyhow!(
+                        "symbol '{}' of exchange '{}' is mapped to '{}' and '{}'",
+                        venue_symbol,
+                        exchange,
+                        other,
+                        instrument.symbol
+                    )
+                    .into());
+                }
+            }
+            registry
+                .instruments
+                .insert(instrument.symbol.clone(), instrument);
+        }
+
+        Ok(registry)
+    }
+
+    /// Sets the rates converting the prices of the exchanges quoting an
+    /// instrument in another currency.
+    pub fn with_rates(mut self, rates: FxRates) -> Self {
+        self.rates = rates;
+        self
+    }
+
+    /// Loads the instruments of the configuration and of the instrument file
+    /// of the configuration, if any.
+    pub fn load(config: &Configuration) -> Result<Self> {
+        let mut instruments = config.instruments.clone();
+        if let Some(path) = &config.instruments_path {
+            instruments.extend(read_instruments(path)?);
+        }
+        let rates = FxRates::new(config.fx_rates.clone())?;
+
+        Ok(Self::new(instruments)?.with_rates(rates))
+    }
+
+    /// Returns the reference data of an instrument.
+    pub fn get(&self, symbol: &str) -> Option<&Instrument> {
+        self.instruments.get(symbol)
+    }
+
+    /// Returns the canonical symbol of the instrument of an exchange channel.
+    ///
+    /// The channel symbol is used when it is not mapped to an instrument.
+    pub fn symbol_of(&self, exchange: &str, channel: &str) -> String {
+        let symbol = normalize_symbol(channel);
+        self.venues
+            .get(&(exchange.to_lowercase(), symbol.clone()))
+            .cloned()
+            .unwrap_or(symbol)
+    }
+
+    /// Returns the rates of the registry.
+    pub fn rates(&self) -> &FxRates {
+        &self.rates
+    }
+
+    /// Returns the rate of each exchange quoting an instrument in another
+    /// currency, `None` if the rate is unknown.
+    pub fn rates_of(&self, symbol: &str) -> Vec<(Exchange, Option<Decimal>)> {
+        let instrument = match self.get(symbol) {
+            Some(instrument) => instrument,
+            None => return vec![],
+        };
+
+        instrument
+            .venue_quotes
+            .iter()
+            .map(|(exchange, quote)| {
+                let rate = self.rates.rate(quote, &instrument.quote);
+                (Exchange::new(exchange), rate)
+            })
+            .collect()
+    }
+
+    /// Returns the fees of each exchange trading an instrument with fees.
+    pub fn fees_of(&self, symbol: &str) -> Vec<(Exchange, Fees)> {
+        self.get(symbol)
+            .map(|instrument| {
+                instrument
+                    .fees
+                    .iter()
+                    .map(|(exchange, fees)| (Exchange::new(exchange), *fees))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Updates the rate following the mid price of the book of an instrument,
+    /// if any.
+    ///
+    /// Returns `true` if the rate changed.
+    pub fn update_rate(
+        &mut self,
+        symbol: &str,
+        bid_book: &BookQueue,
+        ask_book: &BookQueue,
+    ) -> bool {
+        self.rates.update(symbol, bid_book, ask_book)
+    }
+}
+
+/// The [`TickBook`] type is the levels of the exchanges of an instrument at the
+/// prices they publish, aggregated at the ticks of the instrument.
+#[derive(Clone, Debug)]
+pub struct TickBook {
+    instrument: Instrument,
+    /// Amount by exchange, side of the book and published price.
+    levels: HashMap<(Exchange, BookKind), BTreeMap<Decimal, Decimal>>,
+}
+
+impl TickBook {
+    /// Creates new empty [`TickBook`] of an instrument.
+    pub fn new(instrument: Instrument) -> Self {
+        Self {
+            instrument,
+            levels: HashMap::new(),
+        }
+    }
+
+    /// Applies a book update of an exchange, and returns the update of the
+    /// level at the tick of its price.
+    ///
+    /// The amounts of the published prices rounded to the same tick add up.
+    /// Returns `None` if the level is invalid, or if its tick or amount
+    /// overflows.
+    pub fn apply(&mut self, update: BookUpdate) -> Option<BookUpdate> {
+        let (kind, level, deleted) = match update {
+            BookUpdate::Level(kind, level) => (kind, level, false),
+            BookUpdate::Delete(kind, level) => (kind, level, true),
+            BookUpdate::Clear(exchange) => {
+                self.levels.remove(&(exchange.clone(), BookKind::Bids));
+                self.levels.remove(&(exchange.clone(), BookKind::Asks));
+                return Some(BookUpdate::Clear(exchange));
+            }
+            update => return Some(update),
+        };
+
+        let valid = level.price > Decimal::ZERO
+            && !level.amount.is_sign_negative()
+            && self.instrument.round_amount(level.amount).is_some();
+        let tick = valid
+            .then(|| self.instrument.round_price(&kind, level.price))
+            .flatten()
+            .filter(|tick| !tick.is_zero());
+        let tick = match tick {
+            Some(tick) => tick,
+            None => {
+                tracing::warn!(
+                    "dropping invalid level {:?} of '{}'",
+                    level,
+                    self.instrument.symbol
+                );
+                return None;
+            }
+        };
+
+        let levels = self
+            .levels
+            .entry((level.exchange.clone(), kind.clone()))
+            .or_default();
+        let previous = if deleted || level.amount.is_zero() {
+            levels.remove(&level.price)
+        } else {
+            levels.insert(level.price, level.amount)
+        };
+
+        let tick_size = self.instrument.tick_size;
+        let bucket = match kind {
+            BookKind::Bids => (
+                Bound::Included(tick),
+                tick.checked_add(tick_size)
+                    .map_or(Bound::Unbounded, Bound::Excluded),
+            ),
+            BookKind::Asks => (
+                tick.checked_sub(tick_size)
+                    .map_or(Bound::Unbounded, Bound::Excluded),
+                Bound::Included(tick),
+            ),
+        };
+        let amount = levels
+            .range(bucket)
+            .try_fold(Decimal::ZERO, |sum, (_, amount)| sum.checked_add(*amount))
+            .and_then(|amount| self.instrument.round_amount(amount));
+        let amount = match amount {
+            Some(amount) => amount,
+            None => {
+                match previous {
+                    Some(previous) => levels.insert(level.price, previous),
+                    None => levels.remove(&level.price),
+                };
+                tracing::warn!(
+                    "dropping level {:?} of '{}' overflowing its tick",
+                    level,
+                    self.instrument.symbol
+                );
+                return None;
+            }
+        };
+
+        Some(BookUpdate::level(
+            kind,
+            Level::new(level.exchange, tick, amount),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::prelude::{BookKind, Exchange, FxRate};
+
+    fn btcusd() -> Instrument {
+        Instrument {
+            symbol: "BTC-USD".into(),
+            base: "btc".into(),
+            quote: "usd".into(),
+            tick_size: dec!(0.5),
+            lot_size: dec!(0.001),
+            price_precision: 1,
+            venues: HashMap::from([("Kraken".into(), "XBT/USD".into())]),
+            venue_quotes: HashMap::new(),
+            fees: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn registry_maps_exchange_symbols() {
+        let registry = InstrumentRegistry::new(vec![btcusd()]).unwrap();
+
+        assert_eq!(registry.symbol_of("kraken", "XBT/USD"), "btcusd");
+        assert_eq!(registry.symbol_of("bitstamp", "btcusd"), "btcusd");
+        assert_eq!(registry.symbol_of("bitstamp", "ETH-USD"), "ethusd");
+        assert_eq!(registry.get("btcusd").unwrap().quote, "usd");
+    }
+
+    #[test]
+    fn registry_returns_rates_of_exchanges_quoting_in_other_currency() {
+        let instrument = Instrument {
+            venue_quotes: HashMap::from([("binance".into(), "usdt".into())]),
+            ..btcusd()
+        };
+        let rates = FxRates::new(vec![FxRate {
+            base: "usdt".into(),
+            quote: "usd".into(),
+            rate: Some(dec!(0.999)),
+            path: None,
+            instrument: None,
+        }])
+        .unwrap();
+        let registry = InstrumentRegistry::new(vec![instrument]).unwrap();
+        assert_eq!(registry.rates_of("btcusd"), vec![(Exchange::BINANCE, None)]);
+
+        let registry = registry.with_rates(rates);
+        assert_eq!(
+            registry.rates_of("btcusd"),
+            vec![(Exchange::BINANCE, Some(dec!(0.999)))]
+        );
+        assert!(registry.rates_of("ethusd").is_empty());
+    }
+
+    #[test]
+    fn registry_rejects_invalid_instruments() {
+        let instrument = Instrument {
+            tick_size: dec!(0),
+            ..btcusd()
+        };
+        assert!(InstrumentRegistry::new(vec![instrument]).is_err());
+
+        let instrument = Instrument {
+            symbol: "xbtusd".into(),
+            ..btcusd()
+        };
+        assert!(InstrumentRegistry::new(vec![btcusd(), instrument]).is_err());
+    }
+
+    #[test]
+    fn levels_are_rounded_to_increments_away_from_the_spread() {
+        let mut ticks = TickBook::new(btcusd());
+        let update = |kind, price, amount| {
+            BookUpdate::level(kind, Level::new(Exchange::BITSTAMP, price, amount))
+        };
+
+        assert_eq!(
+            ticks.apply(update(BookKind::Bids, dec!(100.26), dec!(1.0019))),
+            Some(update(BookKind::Bids, dec!(100), dec!(1.001)))
+        );
+        assert_eq!(
+            ticks.apply(update(BookKind::Asks, dec!(100.26), dec!(1))),
+            Some(update(BookKind::Asks, dec!(100.5), dec!(1)))
+        );
+        assert_eq!(
+            ticks
+                .apply(update(BookKind::Bids, dec!(99.74), dec!(1)))
+                .map(|update| match update {
+                    BookUpdate::Level(_, level) => level.price.to_string(),
+                    _ => String::new(),
+                }),
+            Some("99.5".into())
+        );
+        assert_eq!(
+            ticks.apply(update(BookKind::Asks, dec!(101), dec!(0.0004))),
+            Some(update(BookKind::Asks, dec!(101), dec!(0.0004)))
+        );
+        assert_eq!(ticks.apply(update(BookKind::Bids, dec!(-1), dec!(1))), None);
+        assert_eq!(
+            ticks.apply(update(BookKind::Bids, dec!(0.2), dec!(1))),
+            None
+        );
+    }
+
+    #[test]
+    fn levels_overflowing_their_increments_are_dropped() {
+        let mut ticks = TickBook::new(btcusd());
+        let update = |price, amount| {
+            BookUpdate::level(
+                BookKind::Asks,
+                Level::new(Exchange::BITSTAMP, price, amount),
+            )
+        };
+
+        let amount = dec!(100000000000000000000000000);
+        assert_eq!(ticks.apply(update(dec!(100), amount)), None);
+        let price = dec!(50000000000000000000000000000);
+        assert_eq!(ticks.apply(update(price, dec!(1))), None);
+
+        let amount = dec!(50000000000000000000000000);
+        ticks.apply(update(dec!(100.1), amount));
+        assert_eq!(ticks.apply(update(dec!(100.2), amount)), None);
+        assert_eq!(
+            ticks.apply(update(dec!(100.3), dec!(1))),
+            Some(update(dec!(100.5), amount + dec!(1)))
+        );
+    }
+
+    #[test]
+    fn levels_rounded_to_the_same_tick_add_up() {
+        let mut ticks = TickBook::new(btcusd());
+        let update = |price, amount| {
+            BookUpdate::level(
+                BookKind::Bids,
+                Level::new(Exchange::BITSTAMP, price, amount),
+            )
+        };
+
+        ticks.apply(update(dec!(100.1), dec!(1)));
+        assert_eq!(
+            ticks.apply(update(dec!(100.3), dec!(2))),
+            Some(update(dec!(100), dec!(3)))
+        );
+        assert_eq!(
+            ticks.apply(update(dec!(100.1), dec!(0))),
+            Some(update(dec!(100), dec!(2)))
+        );
+        assert_eq!(
+            ticks.apply(update(dec!(100.5), dec!(4))),
+            Some(update(dec!(100.5), dec!(4)))
+        );
+        assert!(matches!(
+            ticks.apply(update(dec!(100.3), dec!(0))),
+            Some(BookUpdate::Delete(..))
+        ));
+
+        ticks.apply(update(dec!(100.1), dec!(1)));
+        ticks.apply(BookUpdate::Clear(Exchange::BITSTAMP));
+        assert_eq!(
+            ticks.apply(update(dec!(100.3), dec!(2))),
+            Some(update(dec!(100), dec!(2)))
+        );
+    }
+
+    #[test]
+    fn instruments_are_read_from_file() {
+        let path = std::env::temp_dir().join(format!("instruments-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            [[instruments]]
+            symbol = "ethusd"
+            base = "eth"
+            quote = "usd"
+            tick_size = "0.01"
+            lot_size = "0.0001"
+            price_precision = 2
+            venues = { kraken = "ETH/USD" }
+            "#,
+        )
+        .unwrap();
+
+        let instruments = read_instruments(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let instruments = instruments.unwrap();
+        assert_eq!(instruments.len(), 1);
+        assert_eq!(instruments[0].tick_size, dec!(0.01));
+        assert_eq!(instruments[0].venues["kraken"], "ETH/USD");
+    }
+}
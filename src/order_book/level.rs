@@ -0,0 +1,96 @@
+//! Price level type.
+//!
+//! This module defines the in-memory representation of a price level.
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::{Book, ConsolidatedBook, Exchange};
+
+/// The [`Level`] type is the amount available at a price on an exchange.
+///
+/// Prices and amounts are parsed when messages are decoded and only converted
+/// back to strings when a [`Book`] is sent to clients.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Level {
+    pub exchange: Exchange,
+    pub price: Decimal,
+    pub amount: Decimal,
+    /// Price in the quote currency of the exchange, before conversion.
+    pub raw_price: Decimal,
+    /// Price of taking the level, fees included.
+    pub taker_price: Decimal,
+    /// Price of joining the level, fees included.
+    pub maker_price: Decimal,
+}
+
+impl Level {
+    /// Creates new level with the specified exchange, price and amount.
+    pub fn new(exchange: Exchange, price: Decimal, amount: Decimal) -> Self {
+        Self {
+            exchange,
+            price,
+            amount,
+            raw_price: price,
+            taker_price: price,
+            maker_price: price,
+        }
+    }
+}
+
+/// The [`ConsolidatedLevel`] type is the amount available at a price across exchanges.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ConsolidatedLevel {
+    pub price: Decimal,
+    pub amount: Decimal,
+    /// The levels of each exchange at the price, largest amount first.
+    pub levels: Vec<Level>,
+}
+
+impl ConsolidatedLevel {
+    /// Creates new consolidated level from the levels of each exchange at a price.
+    pub fn new(price: Decimal, mut levels: Vec<Level>) -> Self {
+        levels.sort_by(|l, r| {
+            r.amount
+                .cmp(&l.amount)
+                .then_with(|| l.exchange.as_ref().cmp(r.exchange.as_ref()))
+        });
+        let amount = levels.iter().map(|level| level.amount).sum();
+
+        Self {
+            price,
+            amount,
+            levels,
+        }
+    }
+}
+
+impl From<&Level> for Book {
+    fn from(level: &Level) -> Self {
+        Book {
+            exchange: level.exchange.as_ref().into(),
+            price: level.price.to_string(),
+            amount: level.amount.to_string(),
+            symbol: String::new(),
+            raw_price: level.raw_price.to_string(),
+            taker_price: level.taker_price.to_string(),
+            maker_price: level.maker_price.to_string(),
+        }
+    }
+}
+
+impl From<Level> for Book {
+    fn from(level: Level) -> Self {
+        Book::from(&level)
+    }
+}
+
+impl From<&ConsolidatedLevel> for ConsolidatedBook {
+    fn from(level: &ConsolidatedLevel) -> Self {
+        ConsolidatedBook {
+            price: level.price.to_string(),
+            amount: level.amount.to_string(),
+            books: level.levels.iter().map(Book::from).collect(),
+        }
+    }
+}
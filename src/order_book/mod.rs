@@ -1,8 +1,27 @@
 mod book;
 mod exchange;
+mod fee;
+mod fx;
+mod impact;
+mod instrument;
+mod level;
 mod ser;
+mod spread;
+mod update;
 
 pub use book::order_book_client::*;
 pub use book::order_book_server::*;
-pub use book::{Book, BookKind, BookQueue, Empty, Summary};
+pub use book::{
+    Book, BookKind, BookQueue, ConnectionStatus, ConsolidatedBook, ExchangeConnection,
+    ExchangeFill, ExchangeSpread, Impact, ImpactRequest, Side, SlowConsumerPolicy, Summary,
+    SummaryRequest,
+};
 pub use exchange::Exchange;
+pub use fee::Fees;
+pub use fx::{FxRate, FxRates};
+pub use impact::{Fill, FillSize, MarketImpact};
+pub use instrument::{normalize_symbol, Instrument, InstrumentRegistry, TickBook};
+pub use level::{ConsolidatedLevel, Level};
+pub use spread::Spread;
+pub(crate) use spread::{metric_to_string, METRIC_SCALE};
+pub use update::{BookBatch, BookUpdate, ConnectionState};
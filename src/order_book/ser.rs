@@ -8,13 +8,7 @@ impl Serialize for BookQueue {
         S: Serializer,
     {
         let mut state = serializer.serialize_struct("BookQueue", 2)?;
-        let books = self
-            .books
-            .clone()
-            .into_sorted_iter()
-            .rev()
-            .map(|(_, b)| b)
-            .collect::<Vec<_>>();
+        let books = self.take(self.len());
 
         let kind = match self.kind {
             BookKind::Asks => "asks",
@@ -28,14 +22,13 @@ impl Serialize for BookQueue {
 #[cfg(test)]
 mod tests {
     use crate::prelude::*;
+    use rust_decimal_macros::dec;
 
     #[test]
     fn serde_can_serialize_orderbook() {
         let mut order_book = BookQueue::with_capacity(BookKind::Asks, 2);
-        let book = Book::new("2.1", "0.4", "bitstamp");
-        order_book.push(Exchange::Bitstamp, book);
-        let book = Book::new("3.1", "0.1", "binance");
-        order_book.push(Exchange::Binance, book);
+        order_book.push(Level::new(Exchange::BITSTAMP, dec!(2.1), dec!(0.4)));
+        order_book.push(Level::new(Exchange::BINANCE, dec!(3.1), dec!(0.1)));
         insta::assert_json_snapshot!(&order_book);
     }
 }
@@ -0,0 +1,139 @@
+//! Spread type.
+//!
+//! This module implements the top of the book metrics.
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::{BookQueue, Exchange};
+
+/// Number of decimal places kept for the divided metrics.
+pub(crate) const METRIC_SCALE: u32 = 8;
+
+/// The [`Spread`] type is the best bid and best ask of a book.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Spread {
+    pub bid_price: Decimal,
+    pub bid_amount: Decimal,
+    pub ask_price: Decimal,
+    pub ask_amount: Decimal,
+}
+
+impl Spread {
+    /// Returns the consolidated spread of the books across all exchanges.
+    ///
+    /// Returns `None` if a side of the book is empty.
+    pub fn new(bids: &BookQueue, asks: &BookQueue) -> Option<Self> {
+        let bid = bids.best()?;
+        let ask = asks.best()?;
+
+        Some(Self {
+            bid_price: bid.price,
+            bid_amount: bid.amount,
+            ask_price: ask.price,
+            ask_amount: ask.amount,
+        })
+    }
+
+    /// Returns the spread of the books of an exchange.
+    ///
+    /// Returns `None` if a side of the exchange book is empty.
+    pub fn of_exchange(bids: &BookQueue, asks: &BookQueue, exchange: &Exchange) -> Option<Self> {
+        let bid = bids.best_of(exchange)?;
+        let ask = asks.best_of(exchange)?;
+
+        Some(Self {
+            bid_price: bid.price,
+            bid_amount: bid.amount,
+            ask_price: ask.price,
+            ask_amount: ask.amount,
+        })
+    }
+
+    /// Returns the best ask minus the best bid.
+    ///
+    /// The spread is negative when the book is crossed.
+    pub fn spread(&self) -> Decimal {
+        self.ask_price - self.bid_price
+    }
+
+    /// Returns the mid price.
+    pub fn mid_price(&self) -> Decimal {
+        (self.bid_price + self.ask_price) / Decimal::TWO
+    }
+
+    /// Returns the spread in basis points of the mid price.
+    pub fn spread_bps(&self) -> Option<Decimal> {
+        let bps = self.spread().checked_mul(Decimal::from(10_000))?;
+        Some(bps.checked_div(self.mid_price())?.round_dp(METRIC_SCALE))
+    }
+
+    /// Returns the size weighted mid price.
+    ///
+    /// The microprice moves towards the ask when the bid is larger, and
+    /// towards the bid when the ask is larger.
+    pub fn microprice(&self) -> Option<Decimal> {
+        let weighted = self.bid_price * self.ask_amount + self.ask_price * self.bid_amount;
+        let microprice = weighted.checked_div(self.bid_amount + self.ask_amount)?;
+        Some(microprice.round_dp(METRIC_SCALE))
+    }
+}
+
+/// Converts an optional metric to a string, empty when the metric is undefined.
+pub(crate) fn metric_to_string(value: Option<Decimal>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::prelude::{BookKind, Level};
+
+    fn books(bids: &[Level], asks: &[Level]) -> (BookQueue, BookQueue) {
+        let mut bid_book = BookQueue::new(BookKind::Bids);
+        let mut ask_book = BookQueue::new(BookKind::Asks);
+        bids.iter().cloned().for_each(|level| bid_book.push(level));
+        asks.iter().cloned().for_each(|level| ask_book.push(level));
+        (bid_book, ask_book)
+    }
+
+    #[test]
+    fn spread_uses_best_bid_and_best_ask() {
+        let (bids, asks) = books(
+            &[
+                Level::new(Exchange::BINANCE, dec!(99), dec!(1)),
+                Level::new(Exchange::BITSTAMP, dec!(100), dec!(1)),
+                Level::new(Exchange::BINANCE, dec!(100), dec!(2)),
+            ],
+            &[
+                Level::new(Exchange::BINANCE, dec!(101), dec!(1)),
+                Level::new(Exchange::BITSTAMP, dec!(102), dec!(1)),
+            ],
+        );
+
+        let spread = Spread::new(&bids, &asks).unwrap();
+        assert_eq!(spread.spread(), dec!(1));
+        assert_eq!(spread.mid_price(), dec!(100.5));
+        assert_eq!(spread.spread_bps(), Some(dec!(99.50248756)));
+        assert_eq!(spread.microprice(), Some(dec!(100.75)));
+
+        let bitstamp = Spread::of_exchange(&bids, &asks, &Exchange::BITSTAMP).unwrap();
+        assert_eq!(bitstamp.spread(), dec!(2));
+        assert_eq!(bitstamp.microprice(), Some(dec!(101)));
+    }
+
+    #[test]
+    fn spread_is_negative_when_book_is_crossed() {
+        let (bids, asks) = books(
+            &[Level::new(Exchange::BITSTAMP, dec!(101), dec!(1))],
+            &[Level::new(Exchange::BINANCE, dec!(100), dec!(1))],
+        );
+
+        let spread = Spread::new(&bids, &asks).unwrap();
+        assert_eq!(spread.spread(), dec!(-1));
+        assert!(spread.spread_bps().unwrap().is_sign_negative());
+        assert!(Spread::of_exchange(&bids, &asks, &Exchange::BINANCE).is_none());
+    }
+}
@@ -0,0 +1,135 @@
+//! Book update type.
+//!
+//! This module defines the changes applied to an order book.
+
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+
+use super::{BookKind, ConnectionStatus, Exchange, ExchangeConnection, Level};
+
+/// The [`BookUpdate`] type is a change to apply to an order book.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BookUpdate {
+    /// Sets the level at its price.
+    Level(BookKind, Level),
+
+    /// Removes the level at its price.
+    Delete(BookKind, Level),
+
+    /// Removes all the levels of an exchange, before a new snapshot is applied.
+    Clear(Exchange),
+
+    /// Reports a change of the connection to an exchange.
+    Connection(Exchange, ConnectionState),
+}
+
+/// The [`BookBatch`] type is the updates decoded from one exchange message.
+///
+/// A batch is applied to the book of its instrument at once, in the order of
+/// its updates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BookBatch {
+    /// Symbol of the instrument.
+    pub symbol: String,
+    pub updates: Vec<BookUpdate>,
+}
+
+impl BookBatch {
+    /// Creates new batch of updates of an instrument.
+    pub fn new(symbol: impl Into<String>, updates: Vec<BookUpdate>) -> Self {
+        Self {
+            symbol: symbol.into(),
+            updates,
+        }
+    }
+}
+
+/// The [`ConnectionState`] type is the state of the connection to an exchange.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionState {
+    /// The connection is open and subscribed.
+    Connected,
+
+    /// The connection was closed or failed.
+    Disconnected,
+
+    /// A connection will be attempted after the delay.
+    Reconnecting { attempt: u32, delay: Duration },
+}
+
+impl ExchangeConnection {
+    /// Creates the state of the connection to an exchange.
+    pub fn new(exchange: &Exchange, state: &ConnectionState) -> Self {
+        let (status, attempt, delay) = match state {
+            ConnectionState::Connected => (ConnectionStatus::Connected, 0, Duration::ZERO),
+            ConnectionState::Disconnected => (ConnectionStatus::Disconnected, 0, Duration::ZERO),
+            ConnectionState::Reconnecting { attempt, delay } => {
+                (ConnectionStatus::Reconnecting, *attempt, *delay)
+            }
+        };
+
+        ExchangeConnection {
+            exchange: exchange.as_ref().into(),
+            status: status as i32,
+            attempt,
+            retry_delay_ms: delay.as_millis() as u64,
+        }
+    }
+}
+
+impl BookUpdate {
+    /// Creates the update of a level published by an exchange.
+    ///
+    /// Exchanges publish a zero amount to mean the level was deleted.
+    pub fn level(kind: BookKind, level: Level) -> Self {
+        if level.amount.is_zero() {
+            Self::Delete(kind, level)
+        } else {
+            Self::Level(kind, level)
+        }
+    }
+
+    /// Creates the updates of the bid and ask levels published by an exchange.
+    pub fn levels(
+        exchange: &Exchange,
+        bids: Vec<(Decimal, Decimal)>,
+        asks: Vec<(Decimal, Decimal)>,
+    ) -> Vec<Self> {
+        let bids = bids.into_iter().map(|level| (BookKind::Bids, level));
+        let asks = asks.into_iter().map(|level| (BookKind::Asks, level));
+
+        bids.chain(asks)
+            .map(|(kind, (price, amount))| {
+                Self::level(kind, Level::new(exchange.clone(), price, amount))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn zero_amount_level_is_a_delete() {
+        let update = |amount| {
+            BookUpdate::level(
+                BookKind::Bids,
+                Level::new(Exchange::BINANCE, dec!(1.5), amount),
+            )
+        };
+
+        assert!(matches!(
+            update(dec!(0)),
+            BookUpdate::Delete(BookKind::Bids, _)
+        ));
+        assert!(matches!(update(dec!(0.00000000)), BookUpdate::Delete(..)));
+        assert!(matches!(
+            update(dec!(0.1)),
+            BookUpdate::Level(BookKind::Bids, _)
+        ));
+    }
+}
@@ -1,6 +1,5 @@
 pub use super::configuration::Configuration;
 pub use super::error::Error;
-pub use super::integration::runtime;
 pub use super::integration::summary;
 pub use super::integration::summary::SummaryService;
 pub use super::order_book::*;
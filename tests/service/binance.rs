@@ -0,0 +1,137 @@
+use orderbook::configuration::ExchangeConfig;
+use orderbook::prelude::{BookBatch, BookKind, BookQueue, BookUpdate, Exchange, Level};
+use serde_json::json;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{timeout, Duration};
+use tungstenite::Message;
+
+use crate::mock::{start_http_server, start_ws_server};
+use crate::{exchange_config, force_lazy, stream_books};
+
+fn depth_update(first: u64, last: u64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> Message {
+    Message::Text(
+        json!({
+            "e": "depthUpdate",
+            "E": 1,
+            "s": "BTCUSDT",
+            "U": first,
+            "u": last,
+            "b": bids,
+            "a": asks,
+        })
+        .to_string(),
+    )
+}
+
+struct Books {
+    bids: BookQueue,
+    asks: BookQueue,
+}
+
+impl Books {
+    /// Applies the received updates until the books match the expected levels.
+    async fn wait_for(
+        &mut self,
+        rx: &mut mpsc::Receiver<BookBatch>,
+        bids: &[(&str, &str)],
+        asks: &[(&str, &str)],
+    ) {
+        let expected = |levels: &[(&str, &str)]| {
+            levels
+                .iter()
+                .map(|(price, amount)| {
+                    Level::new(
+                        Exchange::BINANCE,
+                        price.parse().unwrap(),
+                        amount.parse().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let (bids, asks) = (expected(bids), expected(asks));
+
+        let result = timeout(Duration::from_secs(5), async {
+            while self.bids.take(10) != bids || self.asks.take(10) != asks {
+                for update in rx.recv().await.expect("book channel closed").updates {
+                    match update {
+                        BookUpdate::Level(BookKind::Bids, level) => self.bids.push(level),
+                        BookUpdate::Level(BookKind::Asks, level) => self.asks.push(level),
+                        BookUpdate::Delete(BookKind::Bids, level) => {
+                            self.bids.remove(&level.exchange, &level.price);
+                        }
+                        BookUpdate::Delete(BookKind::Asks, level) => {
+                            self.asks.remove(&level.exchange, &level.price);
+                        }
+                        BookUpdate::Clear(exchange) => {
+                            self.bids.clear(&exchange);
+                            self.asks.clear(&exchange);
+                        }
+                        BookUpdate::Connection(..) => (),
+                    }
+                }
+            }
+        })
+        .await;
+
+        assert!(
+            result.is_ok(),
+            "unexpected books: bids {:?}, asks {:?}",
+            self.bids.take(10),
+            self.asks.take(10)
+        );
+    }
+}
+
+#[tokio::test]
+async fn binance_depth_is_synchronised_with_snapshot() {
+    force_lazy();
+
+    let snapshots = vec![
+        json!({"lastUpdateId": 5, "bids": [["100", "1"], ["99", "1"]], "asks": [["101", "1"]]})
+            .to_string(),
+        json!({"lastUpdateId": 21, "bids": [["95", "1"]], "asks": [["105", "1"]]}).to_string(),
+    ];
+    let http_url = start_http_server(snapshots).await;
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+
+    let config = ExchangeConfig {
+        snapshot_url: Some(format!("{http_url}/api/v3/depth")),
+        ..exchange_config("binance", "btcusdt", ws_url)
+    };
+    let (tx, mut rx) = mpsc::channel(100);
+    let (stop_tx, stop_rx) = oneshot::channel();
+    tokio::spawn(stream_books(vec![config], tx, stop_rx));
+
+    let subscription = client_rx.recv().await.unwrap();
+    assert!(subscription.to_text().unwrap().contains("btcusdt@depth"));
+
+    server_tx
+        .send(Message::Text(json!({"result": null, "id": 1}).to_string()))
+        .await
+        .unwrap();
+    for message in [
+        depth_update(1, 3, &[("98", "9")], &[]),
+        depth_update(4, 6, &[("100", "2")], &[]),
+        depth_update(7, 8, &[("99", "0.00000000")], &[("102", "1")]),
+    ] {
+        server_tx.send(message).await.unwrap();
+    }
+
+    let mut books = Books {
+        bids: BookQueue::new(BookKind::Bids),
+        asks: BookQueue::new(BookKind::Asks),
+    };
+    books
+        .wait_for(&mut rx, &[("100", "2")], &[("101", "1"), ("102", "1")])
+        .await;
+
+    server_tx
+        .send(depth_update(20, 22, &[("97", "1")], &[]))
+        .await
+        .unwrap();
+    books
+        .wait_for(&mut rx, &[("97", "1"), ("95", "1")], &[("105", "1")])
+        .await;
+
+    let _ = stop_tx.send(true);
+}
@@ -0,0 +1,91 @@
+use orderbook::prelude::BookUpdate;
+use serde_json::json;
+use tokio::sync::{mpsc, oneshot};
+use tungstenite::Message;
+
+use crate::mock::start_ws_server;
+use crate::{exchange_config, force_lazy, recv_json, stream_books};
+
+fn text(value: serde_json::Value) -> Message {
+    Message::Text(value.to_string())
+}
+
+#[tokio::test]
+async fn coinbase_applies_updates_after_snapshot() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let config = exchange_config("coinbase", "btc-usd", ws_url);
+    let (tx, mut rx) = mpsc::channel(100);
+    let (stop_tx, stop_rx) = oneshot::channel();
+    tokio::spawn(stream_books(vec![config], tx, stop_rx));
+
+    let subscription = recv_json(&mut client_rx).await;
+    assert_eq!(subscription["type"], "subscribe");
+    assert_eq!(subscription["product_ids"], json!(["BTC-USD"]));
+    assert_eq!(
+        subscription["channels"],
+        json!(["level2_batch", "heartbeat"])
+    );
+
+    // Payloads of the level2 channel documentation of Coinbase Exchange.
+    for message in [
+        json!({"type": "l2update", "product_id": "BTC-USD", "time": "2019-08-14T20:42:27.265Z", "changes": [["buy", "10101.80000000", "0.162567"]]}),
+        json!({"type": "snapshot", "product_id": "BTC-USD", "bids": [["10101.10", "0.45054140"]], "asks": [["10102.55", "0.57753524"]]}),
+        json!({"type": "l2update", "product_id": "BTC-USD", "time": "2019-08-14T20:42:27.265Z", "changes": [["buy", "10101.80000000", "0.162567"]]}),
+    ] {
+        server_tx.send(text(message)).await.unwrap();
+    }
+
+    let mut batches = vec![];
+    while batches.len() < 2 {
+        match rx.recv().await.unwrap().updates.as_slice() {
+            [BookUpdate::Connection(..)] => (),
+            updates => batches.push(updates.to_vec()),
+        }
+    }
+    assert!(matches!(batches[0][..], [BookUpdate::Clear(_), _, _]));
+    assert!(
+        matches!(batches[1][..], [BookUpdate::Level(_, ref level)] if level.price == "10101.8".parse().unwrap())
+    );
+
+    let _ = stop_tx.send(true);
+}
+
+#[tokio::test]
+async fn coinbase_resubscribes_to_stale_book() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let config = exchange_config("coinbase", "btc-usd", ws_url);
+    let (tx, mut rx) = mpsc::channel(100);
+    let (stop_tx, stop_rx) = oneshot::channel();
+    tokio::spawn(stream_books(vec![config], tx, stop_rx));
+
+    assert_eq!(recv_json(&mut client_rx).await["type"], "subscribe");
+    server_tx
+        .send(text(json!({"type": "snapshot", "product_id": "BTC-USD", "bids": [["10101.10", "0.45054140"]], "asks": [["10102.55", "0.57753524"]]})))
+        .await
+        .unwrap();
+    // The product sequence moves every second without book update.
+    for sequence in 1..=6 {
+        server_tx
+            .send(text(json!({"type": "heartbeat", "sequence": sequence, "last_trade_id": 20, "product_id": "BTC-USD", "time": "2014-11-07T08:19:28.464459Z"})))
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(recv_json(&mut client_rx).await["type"], "unsubscribe");
+    assert_eq!(recv_json(&mut client_rx).await["type"], "subscribe");
+    let mut batches = vec![];
+    while batches.len() < 2 {
+        match rx.recv().await.unwrap().updates.as_slice() {
+            [BookUpdate::Connection(..)] => (),
+            updates => batches.push(updates.to_vec()),
+        }
+    }
+    assert!(matches!(batches[0][..], [BookUpdate::Clear(_), _, _]));
+    assert!(matches!(batches[1][..], [BookUpdate::Clear(_)]));
+
+    let _ = stop_tx.send(true);
+}
@@ -0,0 +1,45 @@
+use orderbook::configuration::ExchangeConfig;
+use orderbook::prelude::{BookUpdate, ConnectionState};
+use serde_json::json;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{Duration, Instant};
+
+use crate::mock::start_ws_server;
+use crate::{exchange_config, force_lazy, recv, recv_json, stream_books};
+
+#[tokio::test]
+async fn silent_connection_is_pinged_then_recycled() {
+    force_lazy();
+
+    let (ws_url, _server_tx, mut client_rx) = start_ws_server().await;
+    let config = ExchangeConfig {
+        ping_interval_secs: Some(1),
+        idle_timeout_secs: Some(2),
+        ..exchange_config("bitstamp", "btcusd", ws_url)
+    };
+    let (tx, mut rx) = mpsc::channel(100);
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let start = Instant::now();
+    tokio::spawn(stream_books(vec![config], tx, stop_rx));
+
+    recv(&mut client_rx).await;
+    assert_eq!(
+        recv_json(&mut client_rx).await,
+        json!({"event": "bts:heartbeat"})
+    );
+
+    assert!(matches!(
+        recv(&mut rx).await.updates[..],
+        [BookUpdate::Connection(_, ConnectionState::Connected)]
+    ));
+    assert!(matches!(
+        recv(&mut rx).await.updates[..],
+        [
+            BookUpdate::Clear(_),
+            BookUpdate::Connection(_, ConnectionState::Disconnected)
+        ]
+    ));
+    assert!(start.elapsed() >= Duration::from_secs(2));
+
+    let _ = stop_tx.send(true);
+}
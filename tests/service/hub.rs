@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+
+use orderbook::configuration::{Configuration, ExchangeConfig};
+use orderbook::integration::adapter::AdapterRegistry;
+use orderbook::integration::hub::{BookHub, BookView};
+use orderbook::prelude::{
+    ConnectionStatus, Error, ExchangeConnection, Fees, FillSize, Instrument, InstrumentRegistry,
+    Side, Summary,
+};
+use rust_decimal_macros::dec;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::time::{sleep, timeout, Duration};
+use tungstenite::Message;
+
+use crate::mock::start_ws_server;
+use crate::{exchange_config, force_lazy, recv_json};
+
+async fn recv_summary(rx: &mut broadcast::Receiver<Summary>) -> Summary {
+    timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("no summary received")
+        .unwrap()
+}
+
+/// Receives the summary reporting the connection to the exchange.
+async fn recv_connected(rx: &mut broadcast::Receiver<Summary>) {
+    let summary = recv_summary(rx).await;
+    assert_eq!(
+        summary.connections[0].status,
+        ConnectionStatus::Connected as i32
+    );
+}
+
+fn config(url: String) -> ExchangeConfig {
+    exchange_config("bitstamp", "btcusd", url)
+}
+
+#[tokio::test]
+async fn hub_shares_exchange_connection_between_subscribers() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let hub = BookHub::start(
+        10,
+        AdapterRegistry::default(),
+        InstrumentRegistry::default(),
+        vec![config(ws_url)],
+        Duration::from_secs(30),
+    );
+    let mut first = hub.subscribe("btcusd").unwrap();
+    assert!(first.latest.is_none());
+    let mut second = hub.subscribe("btcusd").unwrap();
+
+    assert_eq!(recv_json(&mut client_rx).await["event"], "bts:subscribe");
+    let summary = recv_summary(&mut first.summaries).await;
+    assert_eq!(recv_summary(&mut second.summaries).await, summary);
+    assert_eq!(
+        summary.connections,
+        vec![ExchangeConnection {
+            exchange: "bitstamp".into(),
+            status: ConnectionStatus::Connected as i32,
+            attempt: 0,
+            retry_delay_ms: 0,
+        }]
+    );
+
+    server_tx
+        .send(Message::Text(
+            json!({
+                "event": "data",
+                "channel": "order_book_btcusd",
+                "data": {"bids": [["100", "1"]], "asks": [["101", "2"]]},
+            })
+            .to_string(),
+        ))
+        .await
+        .unwrap();
+
+    // The data message is applied at once, with a single summary.
+    let summary = recv_summary(&mut first.summaries).await;
+    assert_eq!(recv_summary(&mut second.summaries).await, summary);
+    assert_eq!(summary.spread, "1");
+    assert_eq!(summary.bids[0].price, "100");
+
+    assert_eq!(hub.subscribe("btcusd").unwrap().latest, Some(summary));
+    assert!(
+        timeout(Duration::from_millis(200), client_rx.recv())
+            .await
+            .is_err(),
+        "unexpected second subscription"
+    );
+
+    server_tx.send(Message::Close(None)).await.unwrap();
+    let summary = recv_summary(&mut first.summaries).await;
+    assert!(summary.bids.is_empty());
+    assert_eq!(
+        summary.connections[0].status,
+        ConnectionStatus::Disconnected as i32
+    );
+    let summary = recv_summary(&mut first.summaries).await;
+    assert_eq!(
+        summary.connections[0].status,
+        ConnectionStatus::Reconnecting as i32
+    );
+    assert_eq!(summary.connections[0].attempt, 1);
+}
+
+#[tokio::test]
+async fn hub_unsubscribes_after_last_subscriber_lingered() {
+    force_lazy();
+
+    let (ws_url, _server_tx, mut client_rx) = start_ws_server().await;
+    let linger = Duration::from_millis(300);
+    let hub = BookHub::start(
+        10,
+        AdapterRegistry::default(),
+        InstrumentRegistry::default(),
+        vec![config(ws_url)],
+        linger,
+    );
+    assert!(
+        timeout(Duration::from_millis(200), client_rx.recv())
+            .await
+            .is_err(),
+        "subscribed without subscriber"
+    );
+
+    let first = hub.subscribe("btcusd").unwrap();
+    let second = hub.subscribe("btcusd").unwrap();
+    assert_eq!(recv_json(&mut client_rx).await["event"], "bts:subscribe");
+
+    drop(first);
+    drop(second);
+    let third = hub.subscribe("btcusd").unwrap();
+    drop(third);
+    assert!(
+        timeout(linger / 2, client_rx.recv()).await.is_err(),
+        "unsubscribed before the linger period"
+    );
+    assert_eq!(recv_json(&mut client_rx).await["event"], "bts:unsubscribe");
+
+    let _fourth = hub.subscribe("btcusd").unwrap();
+    assert_eq!(recv_json(&mut client_rx).await["event"], "bts:subscribe");
+}
+
+#[tokio::test]
+async fn hub_connects_on_first_subscription() {
+    force_lazy();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let hub = BookHub::start(
+        10,
+        AdapterRegistry::default(),
+        InstrumentRegistry::default(),
+        vec![config(url)],
+        Duration::from_secs(30),
+    );
+    assert!(
+        timeout(Duration::from_millis(200), listener.accept())
+            .await
+            .is_err(),
+        "connected without subscriber"
+    );
+
+    let _subscription = hub.subscribe("btcusd").unwrap();
+    timeout(Duration::from_secs(5), listener.accept())
+        .await
+        .expect("not connected on subscription")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn hub_keeps_book_per_instrument() {
+    force_lazy();
+
+    let (btc_url, _btc_tx, mut btc_rx) = start_ws_server().await;
+    let (eth_url, eth_tx, mut eth_rx) = start_ws_server().await;
+    let hub = BookHub::start(
+        10,
+        AdapterRegistry::default(),
+        InstrumentRegistry::default(),
+        vec![
+            exchange_config("bitstamp", "btcusd", btc_url),
+            exchange_config("bitstamp", "ethusd", eth_url),
+        ],
+        Duration::from_secs(30),
+    );
+    assert!(matches!(
+        hub.subscribe(""),
+        Err(Error::UnknownInstrument(_))
+    ));
+    assert!(matches!(
+        hub.subscribe("ltcusd"),
+        Err(Error::UnknownInstrument(_))
+    ));
+
+    let mut eth = hub.subscribe("ethusd").unwrap();
+    assert_eq!(
+        recv_json(&mut eth_rx).await["data"]["channel"],
+        "order_book_ethusd"
+    );
+    recv_connected(&mut eth.summaries).await;
+    eth_tx
+        .send(Message::Text(
+            json!({
+                "event": "data",
+                "channel": "order_book_ethusd",
+                "data": {"bids": [["10", "1"]], "asks": []},
+            })
+            .to_string(),
+        ))
+        .await
+        .unwrap();
+
+    let summary = recv_summary(&mut eth.summaries).await;
+    assert_eq!(summary.symbol, "ethusd");
+    assert_eq!(summary.bids[0].symbol, "ethusd");
+    assert!(
+        timeout(Duration::from_millis(200), btc_rx.recv())
+            .await
+            .is_err(),
+        "subscribed to instrument without subscriber"
+    );
+}
+
+#[tokio::test]
+async fn hub_maps_channels_to_registered_instruments() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let instrument = Instrument {
+        symbol: "btcusd".into(),
+        base: "btc".into(),
+        quote: "usd".into(),
+        tick_size: dec!(1),
+        lot_size: dec!(0.1),
+        price_precision: 0,
+        venues: HashMap::from([("bitstamp".into(), "xbtusd".into())]),
+        venue_quotes: HashMap::new(),
+        fees: HashMap::from([(
+            "bitstamp".into(),
+            Fees {
+                maker: dec!(0),
+                taker: dec!(0.01),
+            },
+        )]),
+    };
+    let hub = BookHub::start(
+        10,
+        AdapterRegistry::default(),
+        InstrumentRegistry::new(vec![instrument]).unwrap(),
+        vec![exchange_config("bitstamp", "xbtusd", ws_url)],
+        Duration::from_secs(30),
+    );
+    assert!(matches!(
+        hub.subscribe("xbtusd"),
+        Err(Error::UnknownInstrument(_))
+    ));
+
+    let mut btc = hub.subscribe("btcusd").unwrap();
+    assert_eq!(
+        recv_json(&mut client_rx).await["data"]["channel"],
+        "order_book_xbtusd"
+    );
+    recv_connected(&mut btc.summaries).await;
+    server_tx
+        .send(Message::Text(
+            json!({
+                "event": "data",
+                "channel": "order_book_xbtusd",
+                "data": {
+                    "bids": [["100.4", "1.25"], ["100.7", "0.05"], ["-1", "1"]],
+                    "asks": [["101.5", "2"]],
+                },
+            })
+            .to_string(),
+        ))
+        .await
+        .unwrap();
+
+    let summary = recv_summary(&mut btc.summaries).await;
+    assert_eq!(summary.symbol, "btcusd");
+    assert_eq!(summary.bids.len(), 1);
+    assert_eq!(summary.bids[0].price, "100");
+    assert_eq!(summary.bids[0].amount, "1.3");
+    assert_eq!(summary.asks[0].price, "102");
+    assert_eq!(summary.asks[0].taker_price, "103");
+    assert_eq!(summary.net_bids[0].taker_price, "99");
+    assert_eq!(summary.consolidated_net_asks[0].price, "103");
+}
+
+#[tokio::test]
+async fn hub_groups_levels_per_view() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let hub = BookHub::start(
+        10,
+        AdapterRegistry::default(),
+        InstrumentRegistry::default(),
+        vec![config(ws_url)],
+        Duration::from_secs(30),
+    );
+    let mut raw = hub.subscribe("btcusd").unwrap();
+    assert_eq!(recv_json(&mut client_rx).await["event"], "bts:subscribe");
+    recv_connected(&mut raw.summaries).await;
+    let data = |bids: Value, asks: Value| {
+        Message::Text(
+            json!({
+                "event": "data",
+                "channel": "order_book_btcusd",
+                "data": {"bids": bids, "asks": asks},
+            })
+            .to_string(),
+        )
+    };
+    server_tx
+        .send(data(
+            json!([["109", "1"], ["101", "2"], ["95", "3"]]),
+            json!([["111", "1"], ["119", "2"]]),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        recv_summary(&mut raw.summaries)
+            .await
+            .consolidated_bids
+            .len(),
+        3
+    );
+
+    let view = BookView {
+        depth: 1,
+        grouping: Some(dec!(10)),
+    };
+    let mut grouped = hub.subscribe_view("btcusd", view).unwrap();
+    let latest = grouped.latest.take().expect("no summary of the view");
+    assert_eq!(latest.consolidated_bids.len(), 1);
+    assert_eq!(latest.consolidated_bids[0].price, "100");
+    assert_eq!(latest.consolidated_bids[0].amount, "3");
+    assert_eq!(latest.consolidated_asks[0].price, "120");
+    assert_eq!(latest.consolidated_asks[0].amount, "3");
+    assert_eq!(latest.bids.len(), 1);
+
+    server_tx
+        .send(data(json!([["99", "4"]]), json!([])))
+        .await
+        .unwrap();
+    // The order book channel publishes snapshots, replacing the book.
+    assert_eq!(
+        recv_summary(&mut raw.summaries)
+            .await
+            .consolidated_bids
+            .len(),
+        1
+    );
+    let summary = recv_summary(&mut grouped.summaries).await;
+    assert_eq!(summary.consolidated_bids[0].price, "90");
+    assert_eq!(summary.consolidated_bids[0].amount, "4");
+}
+
+#[tokio::test]
+async fn hub_fills_order_against_book_subscribed_on_demand() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let hub = BookHub::start(
+        10,
+        AdapterRegistry::default(),
+        InstrumentRegistry::default(),
+        vec![config(ws_url)],
+        Duration::from_secs(30),
+    );
+    let size = FillSize::Quantity(dec!(2));
+    let server = async {
+        assert_eq!(recv_json(&mut client_rx).await["event"], "bts:subscribe");
+        server_tx
+            .send(Message::Text(
+                json!({
+                    "event": "data",
+                    "channel": "order_book_btcusd",
+                    "data": {"bids": [["99", "1"]], "asks": [["101", "1"], ["103", "4"]]},
+                })
+                .to_string(),
+            ))
+            .await
+            .unwrap();
+    };
+    let (impact, _) = tokio::join!(
+        hub.impact("btcusd", Side::Buy, size, Duration::from_secs(5)),
+        server
+    );
+
+    let impact = impact.unwrap();
+    assert!(impact.complete);
+    assert_eq!(impact.average_price(), Some(dec!(102)));
+    assert_eq!(impact.worst_price, Some(dec!(103)));
+    assert_eq!(impact.slippage(), Some(dec!(2)));
+    assert!(matches!(
+        hub.impact("ltcusd", Side::Sell, size, Duration::ZERO).await,
+        Err(Error::UnknownInstrument(_))
+    ));
+}
+
+#[tokio::test]
+async fn hub_fills_order_against_book_updated_after_request() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let hub = BookHub::start(
+        10,
+        AdapterRegistry::default(),
+        InstrumentRegistry::default(),
+        vec![config(ws_url)],
+        Duration::from_secs(30),
+    );
+    let data = |ask: &str| {
+        Message::Text(
+            json!({
+                "event": "data",
+                "channel": "order_book_btcusd",
+                "data": {"bids": [["99", "1"]], "asks": [[ask, "1"]]},
+            })
+            .to_string(),
+        )
+    };
+    let mut subscription = hub.subscribe("btcusd").unwrap();
+    recv_json(&mut client_rx).await;
+    recv_connected(&mut subscription.summaries).await;
+    server_tx.send(data("101")).await.unwrap();
+    recv_summary(&mut subscription.summaries).await;
+    // The book lingers without subscriber until the next request.
+    drop(subscription);
+
+    let size = FillSize::Quantity(dec!(1));
+    let server = async {
+        sleep(Duration::from_millis(100)).await;
+        server_tx.send(data("105")).await.unwrap();
+    };
+    let (impact, _) = tokio::join!(
+        hub.impact("btcusd", Side::Buy, size, Duration::from_secs(5)),
+        server
+    );
+    assert_eq!(impact.unwrap().worst_price, Some(dec!(105)));
+}
+
+#[tokio::test]
+async fn hub_can_publish_configured_books() {
+    force_lazy();
+
+    let config = Configuration::new().expect("failed to retrieve configuration");
+    let instruments =
+        InstrumentRegistry::load(&config).expect("failed to load instrument registry");
+    let hub = BookHub::start(
+        10,
+        AdapterRegistry::default(),
+        instruments,
+        config.exchanges,
+        Duration::from_secs(30),
+    );
+    let mut subscription = hub.subscribe("btcusd").unwrap();
+
+    let summary = timeout(Duration::from_secs(20), async {
+        loop {
+            match subscription.summaries.recv().await {
+                Ok(summary) if !summary.bids.is_empty() => return summary,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => (),
+                Err(e) => panic!("summaries closed: {e}"),
+            }
+        }
+    })
+    .await
+    .expect("no book received");
+    assert_eq!(summary.symbol, "btcusd");
+}
@@ -0,0 +1,62 @@
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use orderbook::prelude::{BookKind, BookUpdate, ConnectionState};
+use serde_json::json;
+use tokio::sync::{mpsc, oneshot};
+use tungstenite::Message;
+
+use crate::mock::start_ws_server;
+use crate::{exchange_config, force_lazy, recv, recv_json, stream_books};
+
+fn gzip(value: serde_json::Value) -> Message {
+    let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(value.to_string().as_bytes()).unwrap();
+    Message::Binary(encoder.finish().unwrap())
+}
+
+#[tokio::test]
+async fn huobi_compressed_frames_are_decoded() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let config = exchange_config("huobi", "btcusdt", ws_url);
+    let (tx, mut rx) = mpsc::channel(100);
+    let (stop_tx, stop_rx) = oneshot::channel();
+    tokio::spawn(stream_books(vec![config], tx, stop_rx));
+
+    let subscription = recv(&mut client_rx).await;
+    assert!(subscription
+        .to_text()
+        .unwrap()
+        .contains("market.btcusdt.depth.step0"));
+
+    server_tx
+        .send(gzip(json!({"ping": 1492420473027_u64})))
+        .await
+        .unwrap();
+    assert_eq!(
+        recv_json(&mut client_rx).await,
+        json!({"pong": 1492420473027_u64})
+    );
+
+    server_tx
+        .send(gzip(json!({
+            "ch": "market.btcusdt.depth.step0",
+            "ts": 1489474082831_u64,
+            "tick": {"bids": [[9999.38, 0.5]], "asks": [], "version": 100},
+        })))
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv(&mut rx).await.updates[..],
+        [BookUpdate::Connection(_, ConnectionState::Connected)]
+    ));
+    assert!(matches!(
+        recv(&mut rx).await.updates[..],
+        [BookUpdate::Clear(_), BookUpdate::Level(BookKind::Bids, ref level)]
+            if level.price.to_string() == "9999.38"
+    ));
+
+    let _ = stop_tx.send(true);
+}
@@ -1,7 +1,21 @@
-mod runtime;
+mod binance;
+mod coinbase;
+mod heartbeat;
+mod hub;
+mod huobi;
+mod mock;
+mod reconnect;
+mod unsubscribe;
 
 use once_cell::sync::Lazy;
+use orderbook::configuration::ExchangeConfig;
+use orderbook::integration::api_service::{ApiService, ChannelCommand};
+use orderbook::prelude::{BookBatch, Exchange};
 use orderbook::telemetry::Tracer;
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{timeout, Duration};
+use tungstenite::Message;
 
 pub static TRACER: Lazy<()> = Lazy::new(|| {
     if std::env::var("TEST_LOG").is_ok() {
@@ -12,3 +26,56 @@ pub static TRACER: Lazy<()> = Lazy::new(|| {
 pub fn force_lazy() {
     Lazy::force(&TRACER);
 }
+
+/// Creates the configuration of an exchange channel served at an url.
+pub fn exchange_config(exchange: &str, channel: &str, url: String) -> ExchangeConfig {
+    ExchangeConfig {
+        exchange: exchange.into(),
+        channel: channel.into(),
+        channels: vec![],
+        instrument: None,
+        url,
+        snapshot_url: None,
+        ping_interval_secs: None,
+        idle_timeout_secs: None,
+        credential: None,
+    }
+}
+
+/// Receives the next message of a channel, failing after five seconds.
+pub async fn recv<T>(rx: &mut mpsc::Receiver<T>) -> T {
+    timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("no message received")
+        .unwrap()
+}
+
+/// Receives the next message of a channel as a JSON value.
+pub async fn recv_json(rx: &mut mpsc::Receiver<Message>) -> Value {
+    match recv(rx).await {
+        Message::Text(text) => serde_json::from_str(&text).unwrap(),
+        message => panic!("unexpected message {message:?}"),
+    }
+}
+
+/// Streams the books of the exchange channels, each subscribed at once, until a stop
+/// is requested.
+pub async fn stream_books(
+    config: Vec<ExchangeConfig>,
+    book_sender: mpsc::Sender<BookBatch>,
+    stop: oneshot::Receiver<bool>,
+) {
+    let mut api = ApiService::new(10);
+    for channel in config.iter().flat_map(ExchangeConfig::per_channel) {
+        api.connect(&channel)
+            .await
+            .expect("failed to connect to exchange");
+        api.commands(&Exchange::new(&channel.exchange), &channel.symbol())
+            .unwrap()
+            .send(ChannelCommand::Subscribe(channel.channel.clone()))
+            .await
+            .unwrap();
+    }
+
+    api.watch(book_sender, stop).await;
+}
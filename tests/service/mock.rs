@@ -0,0 +1,86 @@
+//! Mock exchange servers.
+
+use std::collections::VecDeque;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tungstenite::Message;
+
+/// Starts an HTTP server replying to each request with the next body.
+///
+/// Returns the server url.
+pub async fn start_http_server(bodies: Vec<String>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut bodies = VecDeque::from(bodies);
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buf[..n]),
+                }
+            }
+
+            let body = bodies.pop_front().unwrap_or_default();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+
+    format!("http://{addr}")
+}
+
+/// Starts a WebSocket server accepting connections one at a time.
+///
+/// Returns the server url, the sender of the messages to write to the client
+/// and the receiver of the messages read from the client. Sending a close
+/// frame closes the current connection and waits for the next one.
+pub async fn start_ws_server() -> (String, mpsc::Sender<Message>, mpsc::Receiver<Message>) {
+    start_ws_server_on("127.0.0.1:0").await
+}
+
+/// Starts a WebSocket server like [`start_ws_server`], listening on an address.
+pub async fn start_ws_server_on(
+    addr: &str,
+) -> (String, mpsc::Sender<Message>, mpsc::Receiver<Message>) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (server_tx, mut server_rx) = mpsc::channel::<Message>(100);
+    let (client_tx, client_rx) = mpsc::channel(100);
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let (mut sink, mut stream) = socket.split();
+
+            loop {
+                tokio::select! {
+                    Some(message) = server_rx.recv() => {
+                        let close = message.is_close();
+                        if sink.send(message).await.is_err() || close {
+                            break;
+                        }
+                    }
+                    message = stream.next() => match message {
+                        Some(Ok(message)) => {
+                            let _ = client_tx.send(message).await;
+                        }
+                        _ => break,
+                    },
+                }
+            }
+        }
+    });
+
+    (format!("ws://{addr}"), server_tx, client_rx)
+}
@@ -0,0 +1,106 @@
+use orderbook::integration::api_service::{ApiService, ChannelCommand};
+use orderbook::prelude::{BookBatch, BookUpdate, ConnectionState, Exchange};
+use serde_json::json;
+use tokio::sync::{mpsc, oneshot};
+use tungstenite::Message;
+
+use crate::mock::{start_ws_server, start_ws_server_on};
+use crate::{exchange_config, force_lazy, recv, recv_json, stream_books};
+
+#[tokio::test]
+async fn exchange_service_reconnects_and_resubscribes() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let config = exchange_config("bitstamp", "btcusd", ws_url);
+    let (tx, mut rx) = mpsc::channel(100);
+    let (stop_tx, stop_rx) = oneshot::channel();
+    tokio::spawn(stream_books(vec![config], tx, stop_rx));
+
+    let subscription = recv(&mut client_rx).await;
+    assert!(matches!(
+        recv(&mut rx).await.updates[..],
+        [BookUpdate::Connection(_, ConnectionState::Connected)]
+    ));
+
+    server_tx.send(Message::Close(None)).await.unwrap();
+    assert!(matches!(
+        recv(&mut rx).await.updates[..],
+        [
+            BookUpdate::Clear(_),
+            BookUpdate::Connection(_, ConnectionState::Disconnected)
+        ]
+    ));
+    assert!(matches!(
+        recv(&mut rx).await.updates[..],
+        [BookUpdate::Connection(
+            _,
+            ConnectionState::Reconnecting { attempt: 1, .. }
+        )]
+    ));
+
+    assert_eq!(recv(&mut client_rx).await, subscription);
+    assert!(matches!(
+        recv(&mut rx).await.updates[..],
+        [BookUpdate::Connection(_, ConnectionState::Connected)]
+    ));
+
+    server_tx
+        .send(Message::Text(
+            json!({
+                "event": "data",
+                "channel": "order_book_btcusd",
+                "data": {"bids": [["100", "1"]], "asks": []},
+            })
+            .to_string(),
+        ))
+        .await
+        .unwrap();
+    assert!(matches!(
+        recv(&mut rx).await.updates[..],
+        [BookUpdate::Clear(_), BookUpdate::Level(..)]
+    ));
+
+    let _ = stop_tx.send(true);
+}
+
+#[tokio::test]
+async fn exchange_service_retries_failed_first_connection() {
+    force_lazy();
+
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let config = exchange_config("bitstamp", "btcusd", format!("ws://{addr}"));
+    let mut api = ApiService::new(10);
+    api.add(&config).unwrap();
+    let commands = api.commands(&Exchange::BITSTAMP, "btcusd").unwrap();
+    commands
+        .send(ChannelCommand::Subscribe("btcusd".into()))
+        .await
+        .unwrap();
+
+    let (tx, mut rx) = mpsc::channel::<BookBatch>(100);
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let test = async move {
+        assert!(matches!(
+            recv(&mut rx).await.updates[..],
+            [BookUpdate::Connection(
+                _,
+                ConnectionState::Reconnecting { attempt: 1, .. }
+            )]
+        ));
+
+        let (_, _server_tx, mut client_rx) = start_ws_server_on(&addr.to_string()).await;
+        assert_eq!(recv_json(&mut client_rx).await["event"], "bts:subscribe");
+        assert!(matches!(
+            recv(&mut rx).await.updates[..],
+            [BookUpdate::Connection(_, ConnectionState::Connected)]
+        ));
+
+        let _ = stop_tx.send(true);
+    };
+
+    let ((), ()) = tokio::join!(api.watch(tx, stop_rx), test);
+}
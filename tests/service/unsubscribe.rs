@@ -0,0 +1,265 @@
+use orderbook::integration::api_service::{ApiService, ChannelCommand};
+use orderbook::integration::bitfinex::BITFINEX;
+use orderbook::prelude::{BookBatch, BookUpdate, ConnectionState, Exchange};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{timeout, Duration};
+use tungstenite::Message;
+
+use crate::mock::start_ws_server;
+use crate::{exchange_config, force_lazy, recv, recv_json};
+
+fn request(event: &str, channel: &str) -> Value {
+    json!({"event": event, "data": {"channel": channel}})
+}
+
+#[tokio::test]
+async fn exchange_service_changes_subscription_and_closes_on_stop() {
+    force_lazy();
+
+    let (ws_url, _server_tx, mut client_rx) = start_ws_server().await;
+    let config = exchange_config("bitstamp", "btcusd", ws_url);
+    let mut api = ApiService::new(10);
+    api.connect(&config).await.unwrap();
+    let commands = api.commands(&Exchange::BITSTAMP, "btcusd").unwrap();
+
+    let (tx, mut rx) = mpsc::channel::<BookBatch>(100);
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let test = async move {
+        commands
+            .send(ChannelCommand::Subscribe("btcusd".into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            recv_json(&mut client_rx).await,
+            request("bts:subscribe", "order_book_btcusd")
+        );
+        assert!(matches!(
+            recv(&mut rx).await.updates[..],
+            [BookUpdate::Connection(_, ConnectionState::Connected)]
+        ));
+
+        commands.send(ChannelCommand::Unsubscribe).await.unwrap();
+        assert_eq!(
+            recv_json(&mut client_rx).await,
+            request("bts:unsubscribe", "order_book_btcusd")
+        );
+        assert_eq!(
+            recv(&mut rx).await,
+            BookBatch::new("btcusd", vec![BookUpdate::Clear(Exchange::BITSTAMP)])
+        );
+
+        commands
+            .send(ChannelCommand::Subscribe("ethusd".into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            recv_json(&mut client_rx).await,
+            request("bts:subscribe", "order_book_ethusd")
+        );
+
+        stop_tx.send(true).unwrap();
+        assert_eq!(
+            recv_json(&mut client_rx).await,
+            request("bts:unsubscribe", "order_book_ethusd")
+        );
+        assert!(recv(&mut client_rx).await.is_close());
+        assert!(matches!(
+            recv(&mut rx).await.updates[..],
+            [
+                BookUpdate::Clear(_),
+                BookUpdate::Connection(_, ConnectionState::Disconnected)
+            ]
+        ));
+    };
+
+    let ((), ()) = tokio::join!(api.watch(tx, stop_rx), test);
+}
+
+#[tokio::test]
+async fn exchange_service_drops_books_received_after_unsubscribe() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let config = exchange_config("bitstamp", "btcusd", ws_url);
+    let mut api = ApiService::new(10);
+    api.connect(&config).await.unwrap();
+    let commands = api.commands(&Exchange::BITSTAMP, "btcusd").unwrap();
+    let data = |channel: &str| {
+        Message::Text(
+            json!({
+                "event": "data",
+                "channel": channel,
+                "data": {"bids": [["100", "1"]], "asks": []},
+            })
+            .to_string(),
+        )
+    };
+
+    let (tx, mut rx) = mpsc::channel::<BookBatch>(100);
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let test = async move {
+        commands
+            .send(ChannelCommand::Subscribe("btcusd".into()))
+            .await
+            .unwrap();
+        recv_json(&mut client_rx).await;
+        assert!(matches!(
+            recv(&mut rx).await.updates[..],
+            [BookUpdate::Connection(_, ConnectionState::Connected)]
+        ));
+
+        commands.send(ChannelCommand::Unsubscribe).await.unwrap();
+        assert_eq!(
+            recv_json(&mut client_rx).await,
+            request("bts:unsubscribe", "order_book_btcusd")
+        );
+        assert_eq!(
+            recv(&mut rx).await,
+            BookBatch::new("btcusd", vec![BookUpdate::Clear(Exchange::BITSTAMP)])
+        );
+        server_tx.send(data("order_book_btcusd")).await.unwrap();
+        assert!(
+            timeout(Duration::from_millis(200), rx.recv())
+                .await
+                .is_err(),
+            "book published after unsubscribe"
+        );
+
+        commands
+            .send(ChannelCommand::Subscribe("ethusd".into()))
+            .await
+            .unwrap();
+        recv_json(&mut client_rx).await;
+        server_tx.send(data("order_book_ethusd")).await.unwrap();
+        assert_eq!(recv(&mut rx).await.symbol, "ethusd");
+
+        stop_tx.send(true).unwrap();
+    };
+
+    let ((), ()) = tokio::join!(api.watch(tx, stop_rx), test);
+}
+
+#[tokio::test]
+async fn bitfinex_unsubscribes_channel_by_id_on_open_connection() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let config = exchange_config("bitfinex", "btcusd", ws_url);
+    let mut api = ApiService::new(10);
+    api.connect(&config).await.unwrap();
+    let commands = api.commands(&BITFINEX, "btcusd").unwrap();
+    let subscribed = |chan_id: u64, symbol: &str| {
+        Message::Text(
+            json!({"event": "subscribed", "channel": "book", "chanId": chan_id, "symbol": symbol})
+                .to_string(),
+        )
+    };
+    let snapshot = |chan_id: u64| Message::Text(json!([chan_id, [[100.5, 1, 2]]]).to_string());
+
+    let (tx, mut rx) = mpsc::channel::<BookBatch>(100);
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let test = async move {
+        commands
+            .send(ChannelCommand::Subscribe("btcusd".into()))
+            .await
+            .unwrap();
+        assert_eq!(recv_json(&mut client_rx).await["symbol"], "tBTCUSD");
+        assert!(matches!(
+            recv(&mut rx).await.updates[..],
+            [BookUpdate::Connection(_, ConnectionState::Connected)]
+        ));
+        server_tx.send(subscribed(42, "tBTCUSD")).await.unwrap();
+        server_tx.send(snapshot(42)).await.unwrap();
+        assert!(matches!(
+            recv(&mut rx).await.updates[..],
+            [BookUpdate::Clear(_), BookUpdate::Level(..)]
+        ));
+
+        commands.send(ChannelCommand::Unsubscribe).await.unwrap();
+        assert_eq!(
+            recv_json(&mut client_rx).await,
+            json!({"event": "unsubscribe", "chanId": 42})
+        );
+        assert_eq!(
+            recv(&mut rx).await,
+            BookBatch::new("btcusd", vec![BookUpdate::Clear(BITFINEX)])
+        );
+        let unsubscribed = json!({"event": "unsubscribed", "status": "OK", "chanId": 42});
+        server_tx
+            .send(Message::Text(unsubscribed.to_string()))
+            .await
+            .unwrap();
+
+        commands
+            .send(ChannelCommand::Subscribe("ethusd".into()))
+            .await
+            .unwrap();
+        assert_eq!(recv_json(&mut client_rx).await["symbol"], "tETHUSD");
+        server_tx.send(subscribed(43, "tETHUSD")).await.unwrap();
+        server_tx.send(snapshot(43)).await.unwrap();
+        assert_eq!(recv(&mut rx).await.symbol, "ethusd");
+
+        stop_tx.send(true).unwrap();
+        assert_eq!(
+            recv_json(&mut client_rx).await,
+            json!({"event": "unsubscribe", "chanId": 43})
+        );
+        assert!(recv(&mut client_rx).await.is_close());
+        assert!(matches!(
+            recv(&mut rx).await.updates[..],
+            [
+                BookUpdate::Clear(_),
+                BookUpdate::Connection(_, ConnectionState::Disconnected)
+            ]
+        ));
+    };
+
+    let ((), ()) = tokio::join!(api.watch(tx, stop_rx), test);
+}
+
+#[tokio::test]
+async fn bitfinex_unsubscribes_channel_once_subscription_is_replied() {
+    force_lazy();
+
+    let (ws_url, server_tx, mut client_rx) = start_ws_server().await;
+    let config = exchange_config("bitfinex", "btcusd", ws_url);
+    let mut api = ApiService::new(10);
+    api.connect(&config).await.unwrap();
+    let commands = api.commands(&BITFINEX, "btcusd").unwrap();
+
+    let (tx, mut rx) = mpsc::channel::<BookBatch>(100);
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let test = async move {
+        commands
+            .send(ChannelCommand::Subscribe("btcusd".into()))
+            .await
+            .unwrap();
+        assert_eq!(recv_json(&mut client_rx).await["symbol"], "tBTCUSD");
+        commands.send(ChannelCommand::Unsubscribe).await.unwrap();
+        assert!(matches!(
+            recv(&mut rx).await.updates[..],
+            [BookUpdate::Connection(_, ConnectionState::Connected)]
+        ));
+        assert_eq!(
+            recv(&mut rx).await,
+            BookBatch::new("btcusd", vec![BookUpdate::Clear(BITFINEX)])
+        );
+
+        let subscribed =
+            json!({"event": "subscribed", "channel": "book", "chanId": 42, "symbol": "tBTCUSD"});
+        server_tx
+            .send(Message::Text(subscribed.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            recv_json(&mut client_rx).await,
+            json!({"event": "unsubscribe", "chanId": 42})
+        );
+
+        stop_tx.send(true).unwrap();
+        assert!(recv(&mut client_rx).await.is_close());
+    };
+
+    let ((), ()) = tokio::join!(api.watch(tx, stop_rx), test);
+}